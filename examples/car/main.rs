@@ -20,6 +20,7 @@ fn main() {
             time_step: 0.002, // 0.002 -> 500 fps
             camera: true,
             environment: true,
+            ..default()
         })
         .run(); // update the bevy joint positions
 }
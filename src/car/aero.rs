@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+
+use crate::{
+    joint::Joint,
+    serialize::{AeroDef, LiftSplitDef},
+    sva::{Force, Vector},
+    wind::Wind,
+};
+
+// where the lift acts when it is split between the axles, in body coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct LiftSplit {
+    front_fraction: f32,
+    front_point: Vector,
+    rear_point: Vector,
+}
+
+impl LiftSplit {
+    pub fn new(front_fraction: f32, front_point: Vector, rear_point: Vector) -> Self {
+        Self {
+            front_fraction,
+            front_point,
+            rear_point,
+        }
+    }
+
+    pub fn from_def(lift_split_def: &LiftSplitDef) -> Self {
+        Self::new(
+            lift_split_def.front_fraction,
+            Vector::from(lift_split_def.front_point),
+            Vector::from(lift_split_def.rear_point),
+        )
+    }
+}
+
+// body aerodynamics on the chassis joint, from the air velocity relative to the body at the
+// centre of pressure. Drag acts along the relative air flow, side force against the sideslip and
+// lift along the body up axis, negative lift coefficients give downforce.
+#[derive(Component)]
+pub struct Aero {
+    drag_coefficient: f32,
+    lift_coefficient: f32,
+    side_coefficient: f32,
+    area: f32,
+    air_density: f32,
+    centre_of_pressure: Vector, // body coordinates
+    lift_split: Option<LiftSplit>,
+    pub airspeed: f32,
+    pub drag: f32,
+    pub lift: f32,
+    pub side_force: f32,
+}
+
+impl Aero {
+    pub fn new(
+        drag_coefficient: f32,
+        lift_coefficient: f32,
+        side_coefficient: f32,
+        area: f32,
+        centre_of_pressure: Vector,
+    ) -> Self {
+        Self {
+            drag_coefficient,
+            lift_coefficient,
+            side_coefficient,
+            area,
+            air_density: 1.225,
+            centre_of_pressure,
+            lift_split: None,
+            airspeed: 0.,
+            drag: 0.,
+            lift: 0.,
+            side_force: 0.,
+        }
+    }
+
+    pub fn with_air_density(mut self, air_density: f32) -> Self {
+        self.air_density = air_density;
+        self
+    }
+
+    pub fn with_lift_split(mut self, lift_split: LiftSplit) -> Self {
+        self.lift_split = Some(lift_split);
+        self
+    }
+
+    pub fn from_def(aero_def: &AeroDef) -> Self {
+        let aero = Self::new(
+            aero_def.drag_coefficient,
+            aero_def.lift_coefficient,
+            aero_def.side_coefficient,
+            aero_def.area,
+            Vector::from(aero_def.centre_of_pressure),
+        )
+        .with_air_density(aero_def.air_density);
+        match &aero_def.lift_split {
+            Some(lift_split_def) => aero.with_lift_split(LiftSplit::from_def(lift_split_def)),
+            None => aero,
+        }
+    }
+}
+
+pub fn aero_system(mut joints: Query<(&mut Joint, &mut Aero)>, wind: Res<Wind>) {
+    for (mut joint, mut aero) in joints.iter_mut() {
+        let x0 = joint.x.inverse();
+        let lat = x0 * Vector::y();
+        let up = x0 * Vector::z();
+
+        // air velocity relative to the body at the centre of pressure
+        let cop = x0.transform_point(aero.centre_of_pressure);
+        let body_velocity = (x0 * joint.v).velocity_point(cop).vel;
+        let air_velocity = body_velocity - wind.velocity_at(cop);
+        let airspeed = air_velocity.norm();
+        if airspeed < 1.0e-3 {
+            aero.airspeed = 0.;
+            aero.drag = 0.;
+            aero.lift = 0.;
+            aero.side_force = 0.;
+            continue;
+        }
+
+        let dynamic_force = 0.5 * aero.air_density * airspeed.powi(2) * aero.area;
+        let sideslip = air_velocity.dot(&lat) / airspeed; // sine of the sideslip angle
+        let drag = aero.drag_coefficient * dynamic_force;
+        let side_force = aero.side_coefficient * dynamic_force * sideslip;
+        let lift = aero.lift_coefficient * dynamic_force;
+
+        let mut force = Force::force_point(-drag * air_velocity / airspeed - side_force * lat, cop);
+        force += match aero.lift_split {
+            Some(split) => {
+                Force::force_point(
+                    split.front_fraction * lift * up,
+                    x0.transform_point(split.front_point),
+                ) + Force::force_point(
+                    (1. - split.front_fraction) * lift * up,
+                    x0.transform_point(split.rear_point),
+                )
+            }
+            None => Force::force_point(lift * up, cop),
+        };
+        joint.f_ext += force;
+
+        aero.airspeed = airspeed;
+        aero.drag = drag;
+        aero.lift = lift;
+        aero.side_force = side_force;
+    }
+}
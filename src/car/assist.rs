@@ -0,0 +1,425 @@
+use bevy::prelude::*;
+
+use crate::{
+    divergence::RollbackState,
+    joint::Joint,
+    serialize::{AbsDef, DriverAssistDef, EscDef, TractionControlDef},
+    sva::Vector,
+};
+
+use super::{
+    brakes::Brake,
+    control::CarControl,
+    differential::{Differential, DriveOutput},
+    electric::ElectricDrive,
+    physics::{BrakeWheel, DrivenWheel},
+    powertrain::Powertrain,
+};
+
+const GRAVITY: f32 = 9.81;
+
+// anti-lock brakes: bleeds the line pressure of a wheel off while it locks and builds it back
+// once the wheel recovers
+#[derive(Debug, Clone)]
+pub struct Abs {
+    pub enabled: bool,
+    target_slip: f32,  // slip ratio, positive
+    release_rate: f32, // modulation per second
+    apply_rate: f32,
+    min_speed: f32, // m/s, off below this so the car can come to rest
+}
+
+impl Abs {
+    pub fn new(target_slip: f32, release_rate: f32, apply_rate: f32, min_speed: f32) -> Self {
+        Self {
+            enabled: true,
+            target_slip,
+            release_rate,
+            apply_rate,
+            min_speed,
+        }
+    }
+
+    pub fn from_def(abs_def: &AbsDef) -> Self {
+        Self {
+            enabled: abs_def.enabled,
+            ..Self::new(
+                abs_def.target_slip,
+                abs_def.release_rate,
+                abs_def.apply_rate,
+                abs_def.min_speed,
+            )
+        }
+    }
+
+    fn modulate(&self, modulation: f32, slip: f32, dt: f32) -> f32 {
+        let rate = if slip < -self.target_slip {
+            -self.release_rate
+        } else {
+            self.apply_rate
+        };
+        (modulation + rate * dt).clamp(0., 1.)
+    }
+}
+
+// traction control: cuts the drive torque of a wheel while it spins up, the drives shared by
+// several wheels follow the worst of them
+#[derive(Debug, Clone)]
+pub struct TractionControl {
+    pub enabled: bool,
+    target_slip: f32,
+    cut_rate: f32, // throttle scale per second
+    recover_rate: f32,
+}
+
+impl TractionControl {
+    pub fn new(target_slip: f32, cut_rate: f32, recover_rate: f32) -> Self {
+        Self {
+            enabled: true,
+            target_slip,
+            cut_rate,
+            recover_rate,
+        }
+    }
+
+    pub fn from_def(traction_control_def: &TractionControlDef) -> Self {
+        Self {
+            enabled: traction_control_def.enabled,
+            ..Self::new(
+                traction_control_def.target_slip,
+                traction_control_def.cut_rate,
+                traction_control_def.recover_rate,
+            )
+        }
+    }
+
+    fn scale(&self, scale: f32, slip: f32, dt: f32) -> f32 {
+        let rate = if slip > self.target_slip {
+            -self.cut_rate
+        } else {
+            self.recover_rate
+        };
+        (scale + rate * dt).clamp(0., 1.)
+    }
+}
+
+// electronic stability control: brakes one wheel for a yaw moment against the error between the
+// measured yaw rate and a steady state bicycle model, limited to what the road can give.
+// Understeer brakes the inner rear wheel, oversteer the outer front.
+#[derive(Debug, Clone)]
+pub struct Esc {
+    pub enabled: bool,
+    wheelbase: f32,
+    understeer_gradient: f32, // rad per m/s^2
+    friction: f32,
+    gain: f32,     // Nm per rad/s
+    deadband: f32, // rad/s
+    max_torque: f32,
+    min_speed: f32,
+}
+
+impl Esc {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        wheelbase: f32,
+        understeer_gradient: f32,
+        friction: f32,
+        gain: f32,
+        deadband: f32,
+        max_torque: f32,
+        min_speed: f32,
+    ) -> Self {
+        Self {
+            enabled: true,
+            wheelbase,
+            understeer_gradient,
+            friction,
+            gain,
+            deadband,
+            max_torque,
+            min_speed,
+        }
+    }
+
+    pub fn from_def(esc_def: &EscDef) -> Self {
+        Self {
+            enabled: esc_def.enabled,
+            ..Self::new(
+                esc_def.wheelbase,
+                esc_def.understeer_gradient,
+                esc_def.friction,
+                esc_def.gain,
+                esc_def.deadband,
+                esc_def.max_torque,
+                esc_def.min_speed,
+            )
+        }
+    }
+
+    pub fn reference_yaw_rate(&self, speed: f32, steer: f32) -> f32 {
+        let yaw_rate = speed * steer / (self.wheelbase + self.understeer_gradient * speed * speed);
+        let limit = self.friction * GRAVITY / speed.abs().max(1.);
+        yaw_rate.clamp(-limit, limit)
+    }
+
+    // brake torque and the wheel (front left, front right, rear left, rear right) it goes on
+    fn intervention(&self, yaw_rate: f32, reference: f32) -> Option<(usize, f32)> {
+        let error = reference - yaw_rate;
+        let torque = (self.gain * (error.abs() - self.deadband)).min(self.max_torque);
+        if torque <= 0. {
+            return None;
+        }
+        // braking the left side turns the car left (positive yaw)
+        let side = if error > 0. { 0 } else { 1 };
+        let understeer = error.signum() == reference.signum();
+        let axle = if understeer { 2 } else { 0 };
+        Some((axle + side, torque))
+    }
+}
+
+// driver assists for one car, between CarControl and the brakes and drives. The controllers run
+// once per step from the estimated wheel slip and chassis yaw rate, and hand their outputs to the
+// brakes (modulation, assist_torque) and drives (throttle_scale) for the next step. Each one is
+// optional and can be switched off on its own to compare assisted and unassisted runs.
+#[derive(Component)]
+pub struct DriverAssist {
+    pub chassis: Entity,
+    pub wheels: [Entity; 4], // front left, front right, rear left, rear right
+    pub steering: Vec<Entity>,
+    wheel_radius: f32,
+    pub abs: Option<Abs>,
+    pub traction_control: Option<TractionControl>,
+    pub esc: Option<Esc>,
+    pub slips: [f32; 4], // estimated slip ratio, positive when driving
+    pub brake_modulation: [f32; 4],
+    pub throttle_scales: [f32; 4],
+    pub esc_torques: [f32; 4],
+    pub speed: f32,
+    pub yaw_rate: f32,
+    pub reference_yaw_rate: f32,
+}
+
+impl DriverAssist {
+    pub fn new(
+        chassis: Entity,
+        wheels: [Entity; 4],
+        steering: Vec<Entity>,
+        wheel_radius: f32,
+    ) -> Self {
+        Self {
+            chassis,
+            wheels,
+            steering,
+            wheel_radius,
+            abs: None,
+            traction_control: None,
+            esc: None,
+            slips: [0.; 4],
+            brake_modulation: [1.; 4],
+            throttle_scales: [1.; 4],
+            esc_torques: [0.; 4],
+            speed: 0.,
+            yaw_rate: 0.,
+            reference_yaw_rate: 0.,
+        }
+    }
+
+    pub fn with_abs(mut self, abs: Abs) -> Self {
+        self.abs = Some(abs);
+        self
+    }
+
+    pub fn with_traction_control(mut self, traction_control: TractionControl) -> Self {
+        self.traction_control = Some(traction_control);
+        self
+    }
+
+    pub fn with_esc(mut self, esc: Esc) -> Self {
+        self.esc = Some(esc);
+        self
+    }
+
+    pub fn from_def(
+        driver_assist_def: &DriverAssistDef,
+        chassis: Entity,
+        wheels: [Entity; 4],
+        steering: Vec<Entity>,
+    ) -> Self {
+        let mut driver_assist =
+            Self::new(chassis, wheels, steering, driver_assist_def.wheel_radius);
+        driver_assist.abs = driver_assist_def.abs.as_ref().map(Abs::from_def);
+        driver_assist.traction_control = driver_assist_def
+            .traction_control
+            .as_ref()
+            .map(TractionControl::from_def);
+        driver_assist.esc = driver_assist_def.esc.as_ref().map(Esc::from_def);
+        driver_assist
+    }
+
+    fn abs_active(&self) -> Option<&Abs> {
+        self.abs.as_ref().filter(|abs| abs.enabled)
+    }
+
+    fn traction_control_active(&self) -> Option<&TractionControl> {
+        self.traction_control
+            .as_ref()
+            .filter(|traction_control| traction_control.enabled)
+    }
+
+    fn esc_active(&self) -> Option<&Esc> {
+        self.esc.as_ref().filter(|esc| esc.enabled)
+    }
+
+    // worst throttle scale over some wheels, for a drive shared between them. None when the
+    // drive has none of this assist's wheels.
+    fn throttle_scale(&self, wheels: &[Entity]) -> Option<f32> {
+        self.wheels
+            .iter()
+            .zip(self.throttle_scales)
+            .filter(|(wheel, _)| wheels.contains(*wheel))
+            .map(|(_, wheel_scale)| wheel_scale)
+            .reduce(f32::min)
+    }
+}
+
+impl RollbackState for DriverAssist {
+    type State = [[f32; 4]; 2];
+    fn save(&self) -> Self::State {
+        [self.brake_modulation, self.throttle_scales]
+    }
+    fn restore(&mut self, state: &Self::State) {
+        [self.brake_modulation, self.throttle_scales] = *state;
+    }
+}
+
+// ground speed of a wheel hub along its heading, m/s
+fn hub_speed(joint: &Joint) -> f32 {
+    let x0 = joint.x.inverse();
+    let hub = x0.transform_point(Vector::zeros());
+    let velocity = (x0 * joint.v).velocity_point(hub).vel;
+    let forward = (x0 * Vector::y()).cross(&Vector::z());
+    let norm = forward.norm();
+    if norm < 1.0e-6 {
+        return 0.;
+    }
+    velocity.dot(&forward) / norm
+}
+
+// assist controllers, once per step
+#[allow(clippy::too_many_arguments)]
+pub fn driver_assist_system(
+    mut assists: Query<&mut DriverAssist>,
+    joints: Query<&Joint>,
+    mut brakes: Query<&mut Brake>,
+    mut brake_wheels: Query<&mut BrakeWheel>,
+    mut driven_wheels: Query<&mut DrivenWheel>,
+    mut powertrains: Query<&mut Powertrain>,
+    mut electric_drives: Query<&mut ElectricDrive>,
+    differentials: Query<&Differential>,
+    control: Res<CarControl>,
+    fixed_time: Res<FixedTime>,
+) {
+    let dt = fixed_time.period.as_secs_f32();
+    // wheels of a drive, through its differential when it has one
+    let drive_wheels = |differential: Option<Entity>, wheels: &[Entity]| match differential {
+        Some(differential) => DriveOutput::Differential(differential).wheels(&differentials),
+        None => wheels.to_vec(),
+    };
+    for mut assist in assists.iter_mut() {
+        // chassis speed along its heading and yaw rate about its up axis
+        let Ok(chassis) = joints.get(assist.chassis) else {
+            continue;
+        };
+        let x0 = chassis.x.inverse();
+        let origin = x0.transform_point(Vector::zeros());
+        let velocity = x0 * chassis.v;
+        let speed = velocity.velocity_point(origin).vel.dot(&(x0 * Vector::x()));
+        let yaw_rate = velocity.w.dot(&(x0 * Vector::z()));
+        let steer_angles: Vec<f32> = assist
+            .steering
+            .iter()
+            .filter_map(|steering| joints.get(*steering).ok().map(|joint| joint.q))
+            .collect();
+        let steer = steer_angles.iter().sum::<f32>() / steer_angles.len().max(1) as f32;
+
+        // longitudinal slip of each wheel, signed so that it is positive when driving
+        for i in 0..4 {
+            assist.slips[i] = joints.get(assist.wheels[i]).map_or(0., |joint| {
+                let vx = hub_speed(joint);
+                let kappa = (joint.qd * assist.wheel_radius - vx) / vx.abs().max(1.);
+                if vx < 0. {
+                    -kappa
+                } else {
+                    kappa
+                }
+            });
+        }
+
+        // abs, while braking above the cut-off speed
+        let braking = control.brake > 0.;
+        for i in 0..4 {
+            let slip = assist.slips[i];
+            assist.brake_modulation[i] = match assist.abs_active() {
+                Some(abs) if braking && speed.abs() > abs.min_speed => {
+                    abs.modulate(assist.brake_modulation[i], slip, dt)
+                }
+                _ => 1.,
+            };
+        }
+
+        // traction control
+        for i in 0..4 {
+            let slip = assist.slips[i];
+            assist.throttle_scales[i] = match assist.traction_control_active() {
+                Some(traction_control) => {
+                    traction_control.scale(assist.throttle_scales[i], slip, dt)
+                }
+                None => 1.,
+            };
+        }
+
+        // esc
+        let reference = assist
+            .esc_active()
+            .map_or(0., |esc| esc.reference_yaw_rate(speed, steer));
+        let intervention = assist
+            .esc_active()
+            .filter(|esc| speed.abs() > esc.min_speed)
+            .and_then(|esc| esc.intervention(yaw_rate, reference));
+        assist.esc_torques = [0.; 4];
+        if let Some((wheel, torque)) = intervention {
+            assist.esc_torques[wheel] = torque;
+        }
+        assist.speed = speed;
+        assist.yaw_rate = yaw_rate;
+        assist.reference_yaw_rate = reference;
+
+        // hand the outputs over to the brakes and drives
+        for i in 0..4 {
+            let wheel = assist.wheels[i];
+            if let Ok(mut brake) = brakes.get_mut(wheel) {
+                brake.modulation = assist.brake_modulation[i];
+                brake.assist_torque = assist.esc_torques[i];
+            } else if let Ok(mut brake) = brake_wheels.get_mut(wheel) {
+                brake.modulation = assist.brake_modulation[i];
+                brake.assist_torque = assist.esc_torques[i];
+            }
+            if let Ok(mut driven_wheel) = driven_wheels.get_mut(wheel) {
+                driven_wheel.throttle_scale = assist.throttle_scales[i];
+            }
+        }
+        // only the drives of this assist's wheels, another car's drives are left alone
+        for mut powertrain in powertrains.iter_mut() {
+            let wheels = drive_wheels(powertrain.differential, &powertrain.wheels);
+            if let Some(scale) = assist.throttle_scale(&wheels) {
+                powertrain.throttle_scale = scale;
+            }
+        }
+        for mut electric_drive in electric_drives.iter_mut() {
+            let wheels = drive_wheels(electric_drive.differential, &electric_drive.wheels);
+            if let Some(scale) = assist.throttle_scale(&wheels) {
+                electric_drive.throttle_scale = scale;
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use bevy::prelude::*;
+
+use crate::{
+    divergence::RollbackState,
+    joint::Joint,
+    lookup::LookupTable,
+    serialize::{BrakeCircuitDef, BrakeDef, BrakeHydraulicsDef},
+};
+
+use super::{control::CarControl, physics::PAD_STATE};
+
+// the pad spring is pulled back to the friction limit (or to zero without a spring) with this
+// time constant
+const PAD_RELEASE_TIME: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrakeCircuit {
+    Front,
+    Rear,
+}
+
+impl BrakeCircuit {
+    pub fn from_def(brake_circuit_def: &BrakeCircuitDef) -> Self {
+        match brake_circuit_def {
+            BrakeCircuitDef::Front => BrakeCircuit::Front,
+            BrakeCircuitDef::Rear => BrakeCircuit::Rear,
+        }
+    }
+}
+
+// master cylinder and lines: pedal to pressure, front/rear bias and a first order lag.
+// Bias is the front share, the stronger circuit gets the full master cylinder pressure.
+#[derive(Component)]
+pub struct BrakeHydraulics {
+    pedal_pressure: LookupTable, // Pa against pedal travel, 0 to 1
+    bias: f32,
+    time_constant: f32,
+    pub pressures: [f32; 2], // front, rear
+}
+
+impl BrakeHydraulics {
+    pub fn new(pedal_pressure: LookupTable, bias: f32, time_constant: f32) -> Self {
+        Self {
+            pedal_pressure,
+            bias,
+            time_constant,
+            pressures: [0., 0.],
+        }
+    }
+
+    pub fn from_def(brake_hydraulics_def: &BrakeHydraulicsDef) -> Result<Self, String> {
+        Ok(Self::new(
+            LookupTable::curve(&brake_hydraulics_def.pedal_pressure)
+                .map_err(|e| format!("pedal pressure: {}", e))?,
+            brake_hydraulics_def.bias,
+            brake_hydraulics_def.time_constant,
+        ))
+    }
+
+    pub fn pressure(&self, circuit: BrakeCircuit) -> f32 {
+        match circuit {
+            BrakeCircuit::Front => self.pressures[0],
+            BrakeCircuit::Rear => self.pressures[1],
+        }
+    }
+
+    fn update(&mut self, pedal: f32, dt: f32) {
+        let pressure = self.pedal_pressure.interpolate_1d(pedal);
+        let scale = self.bias.max(1. - self.bias);
+        let targets = [
+            pressure * self.bias / scale,
+            pressure * (1. - self.bias) / scale,
+        ];
+        for (pressure, target) in self.pressures.iter_mut().zip(targets) {
+            *pressure = if self.time_constant > 0. {
+                (*pressure + dt / self.time_constant * target) / (1. + dt / self.time_constant)
+            } else {
+                target // no lag
+            };
+        }
+    }
+}
+
+impl RollbackState for BrakeHydraulics {
+    type State = [f32; 2];
+    fn save(&self) -> Self::State {
+        self.pressures
+    }
+    fn restore(&mut self, state: &Self::State) {
+        self.pressures = *state;
+    }
+}
+
+// disc brake on a wheel joint. The pads hold the wheel through a stiff spring that slips once its
+// torque reaches the friction limit, so the brake still works at standstill and holds on a slope.
+// The spring deflection is part of the wheel joint state (PAD_STATE), so it is integrated by the
+// same solver as the joints.
+#[derive(Component)]
+pub struct Brake {
+    circuit: BrakeCircuit,
+    pad_friction: f32,
+    piston_area: f32,               // m^2, per side
+    effective_radius: f32,          // m, from the wheel axis to the pad centre
+    handbrake_torque: f32,          // Nm at full handbrake, 0 for no handbrake
+    hold_stiffness: f32,            // Nm/rad
+    hold_damping: f32,              // Nm per rad/s
+    pub hydraulics: Option<Entity>, // line pressure source, only the handbrake works without one
+    pub pressure_torque: f32,       // friction limit from the line pressure
+    pub regen_torque: f32, // part of the brake torque already taken by regenerative braking
+    pub modulation: f32,   // share of the line pressure let through by the abs valves
+    pub assist_torque: f32, // extra friction limit asked for by stability control
+    pub torque: f32,
+}
+
+impl Brake {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        circuit: BrakeCircuit,
+        pad_friction: f32,
+        piston_area: f32,
+        effective_radius: f32,
+        handbrake_torque: f32,
+        hold_stiffness: f32,
+        hold_damping: f32,
+    ) -> Self {
+        Self {
+            circuit,
+            pad_friction,
+            piston_area,
+            effective_radius,
+            handbrake_torque,
+            hold_stiffness,
+            hold_damping,
+            hydraulics: None,
+            pressure_torque: 0.,
+            regen_torque: 0.,
+            modulation: 1.,
+            assist_torque: 0.,
+            torque: 0.,
+        }
+    }
+
+    pub fn with_hydraulics(mut self, hydraulics: Entity) -> Self {
+        self.hydraulics = Some(hydraulics);
+        self
+    }
+
+    pub fn from_def(brake_def: &BrakeDef) -> Self {
+        Self::new(
+            BrakeCircuit::from_def(&brake_def.circuit),
+            brake_def.pad_friction,
+            brake_def.piston_area,
+            brake_def.effective_radius,
+            brake_def.handbrake_torque,
+            brake_def.hold_stiffness,
+            brake_def.hold_damping,
+        )
+    }
+
+    // two pads clamping the disc
+    fn pressure_torque(&self, pressure: f32) -> f32 {
+        2. * self.pad_friction * pressure * self.piston_area * self.effective_radius
+    }
+
+    fn friction_limit(&self, handbrake: f32) -> f32 {
+        (self.modulation * self.pressure_torque - self.regen_torque).max(0.)
+            + self.assist_torque
+            + handbrake * self.handbrake_torque
+    }
+
+    // rad, pad spring of a wheel joint
+    pub fn deflection(joint: &Joint) -> f32 {
+        joint.aux[PAD_STATE]
+    }
+
+    // the pads stick until the spring torque reaches the friction limit, then slip
+    fn deflection_rate(&self, joint: &Joint, limit: f32) -> f32 {
+        let deflection = Self::deflection(joint);
+        if self.hold_stiffness <= 0. {
+            return -deflection / PAD_RELEASE_TIME;
+        }
+        let max_deflection = limit.max(0.) / self.hold_stiffness;
+        let stick_rate = if deflection.abs() >= max_deflection && joint.qd * deflection > 0. {
+            0.
+        } else {
+            joint.qd
+        };
+        stick_rate
+            + (deflection.clamp(-max_deflection, max_deflection) - deflection) / PAD_RELEASE_TIME
+    }
+}
+
+impl RollbackState for Brake {
+    type State = [f32; 3];
+    fn save(&self) -> Self::State {
+        [self.pressure_torque, self.modulation, self.assist_torque]
+    }
+    fn restore(&mut self, state: &Self::State) {
+        [self.pressure_torque, self.modulation, self.assist_torque] = *state;
+    }
+}
+
+pub fn brake_system(mut joints: Query<(&mut Joint, &mut Brake)>, control: Res<CarControl>) {
+    for (mut joint, mut brake) in joints.iter_mut() {
+        let limit = brake.friction_limit(control.handbrake).max(0.);
+        let spring_torque =
+            brake.hold_stiffness * Brake::deflection(&joint) + brake.hold_damping * joint.qd;
+        let torque = -spring_torque.clamp(-limit, limit);
+        brake.torque = torque;
+        joint.tau += torque;
+        joint.aux_d[PAD_STATE] = brake.deflection_rate(&joint, limit);
+    }
+}
+
+// line pressures, once per step
+pub fn brake_update_system(
+    mut hydraulics: Query<&mut BrakeHydraulics>,
+    mut brakes: Query<&mut Brake>,
+    control: Res<CarControl>,
+    fixed_time: Res<FixedTime>,
+) {
+    let dt = fixed_time.period.as_secs_f32();
+    for mut hydraulics in hydraulics.iter_mut() {
+        hydraulics.update(control.brake, dt);
+    }
+
+    for mut brake in brakes.iter_mut() {
+        let pressure = match brake.hydraulics {
+            Some(entity) => match hydraulics.get(entity) {
+                Ok(hydraulics) => hydraulics.pressure(brake.circuit),
+                Err(_) => {
+                    warn!("brake hydraulics {:?} not found, no line pressure", entity);
+                    0.
+                }
+            },
+            None => 0.,
+        };
+        brake.pressure_torque = brake.pressure_torque(pressure);
+    }
+}
@@ -4,17 +4,45 @@ use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 
 use crate::{
+    collision::{Collider, CollisionShape},
     joint::{Base, Joint},
+    mesh::{BoxMesh, Mesh as RBDA_Mesh},
     sva::{Inertia, Matrix, Motion, Vector, Xform},
 };
 
-use super::physics::{BrakeWheel, DrivenWheel, Steering, Suspension, TireContact};
+use super::{
+    aero::{Aero, LiftSplit},
+    assist::{Abs, DriverAssist, Esc, TractionControl},
+    brakes::{Brake, BrakeCircuit, BrakeHydraulics},
+    physics::{
+        AntiRollBar, BrakeWheel, DrivenWheel, KinematicCurve, Steering, SteeringRack, Suspension,
+        SuspensionKinematics, TireCondition, TireContact, TireState,
+    },
+};
+use crate::lookup::LookupTable;
 
 pub fn build_model(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
 ) {
+    build_car(commands, meshes, materials);
+}
+
+// entities of the demo car that the optional systems attach to
+struct CarIds {
+    base_id: Entity,
+    chassis_id: Entity,
+    steering_ids: Vec<Entity>,
+    suspension_ids: Vec<Entity>,
+    wheel_ids: Vec<Entity>,
+}
+
+fn build_car(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) -> CarIds {
     // create base
     let base = Joint::base(Motion::new([0., 0., 9.81], [0., 0., 0.]));
     let base_id = commands.spawn((base, Base, SpatialBundle::default())).id();
@@ -34,11 +62,15 @@ pub fn build_model(
     let mut parent_id: Entity;
     let mut suspension_location: [f32; 2];
     let mut driven_wheel: bool;
+    let mut steering_ids = Vec::new();
+    let mut suspension_ids = Vec::new();
+    let mut wheel_ids = Vec::new();
     // loop through corners and build suspension, steering, and wheels
     for (ind, location) in corner_locations.iter().enumerate() {
         if ind < 2 {
             // add steering to front wheels
             let steering_id = build_steer(commands, *location, chassis_id, corner_names[ind]);
+            steering_ids.push(steering_id);
             parent_id = steering_id; // suspension is attached to steering
             suspension_location = [0., 0.]; // location of suspension relative to steering
             driven_wheel = false;
@@ -56,7 +88,8 @@ pub fn build_model(
             parent_id,
             corner_names[ind],
         );
-        build_wheel(
+        suspension_ids.push(id_susp);
+        let wheel_id = build_wheel(
             commands,
             meshes,
             materials,
@@ -64,7 +97,236 @@ pub fn build_model(
             driven_wheel,
             corner_names[ind],
         );
+        wheel_ids.push(wheel_id);
+    }
+
+    CarIds {
+        base_id,
+        chassis_id,
+        steering_ids,
+        suspension_ids,
+        wheel_ids,
+    }
+}
+
+// the demo car with the steering rack, suspension kinematics, anti-roll bars, disc brakes,
+// driver assists, aero and warm tires, and a stack of crates to push around
+pub fn build_full_model(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) {
+    let car = build_car(commands, meshes, materials);
+
+    // camber gain and bump steer, with steer camber from caster at the front
+    let polynomial =
+        |travel: Vec<f32>, steer: Vec<f32>| Some(KinematicCurve::Polynomial { travel, steer });
+    for (ind, (wheel_id, id_susp)) in car.wheel_ids.iter().zip(&car.suspension_ids).enumerate() {
+        let left = ind % 2 == 0;
+        let side = if left { 1. } else { -1. };
+        let kinematics = if ind < 2 {
+            SuspensionKinematics::new(*id_susp, Some(car.steering_ids[ind]), left).with_curves(
+                polynomial(vec![0., -0.5], vec![0.1 * side]),
+                polynomial(vec![0., -0.05], vec![]),
+                polynomial(vec![0., 0., -0.5], vec![]),
+            )
+        } else {
+            SuspensionKinematics::new(*id_susp, None, left).with_curves(
+                polynomial(vec![0., -0.3], vec![]),
+                polynomial(vec![0., 0.1], vec![]),
+                polynomial(vec![0., 0., -0.5], vec![]),
+            )
+        };
+        commands.entity(*wheel_id).insert(kinematics);
+    }
+
+    // the preload carries the static load, so ride height is the static travel
+    let spring_stiffness: f32 = 1000. * 9.81 / 4. / 0.1;
+    let damping = 0.5 * 2. * (spring_stiffness * (1000. / 4.)).sqrt();
+    for id_susp in car.suspension_ids.iter() {
+        commands.entity(*id_susp).insert(
+            Suspension::new(spring_stiffness, damping).with_spring_curve(
+                None,
+                1000. * 9.81 / 4.,
+                0.1,
+            ),
+        );
+    }
+
+    // steering rack for the front wheels instead of steering each wheel, 30 degrees at full lock
+    for steering_id in car.steering_ids.iter() {
+        commands.entity(*steering_id).remove::<Steering>();
+    }
+    commands.spawn(SteeringRack::new(
+        car.steering_ids[0],
+        car.steering_ids[1],
+        15.,
+        450. * PI / 180.,
+        0.15,
+        0.15 * (30. * PI / 180.).sin(),
+        2.5,
+        1.5,
+        1.,
+        [0., 0.],
+    ));
+
+    // anti-roll bars, stiffer at the front for understeer
+    commands.spawn(AntiRollBar::new(
+        car.suspension_ids[0],
+        car.suspension_ids[1],
+        0.6 * spring_stiffness,
+    ));
+    commands.spawn(AntiRollBar::new(
+        car.suspension_ids[2],
+        car.suspension_ids[3],
+        0.3 * spring_stiffness,
+    ));
+
+    // 100 bar at full pedal after a little dead travel, 60% front bias
+    let hydraulics_id = commands
+        .spawn(BrakeHydraulics::new(
+            LookupTable::curve(&[[0., 0.], [0.05, 0.], [1., 1.0e7]]).unwrap(),
+            0.6,
+            0.05,
+        ))
+        .id();
+    // disc brakes on the hydraulics instead of the simple wheel brakes, the driven rear wheels
+    // carry the handbrake
+    for (ind, wheel_id) in car.wheel_ids.iter().enumerate() {
+        let brake = if ind < 2 {
+            Brake::new(BrakeCircuit::Front, 0.4, 7.7e-4, 0.13, 0., 1.0e4, 500.)
+        } else {
+            Brake::new(BrakeCircuit::Rear, 0.4, 6.0e-4, 0.12, 600., 1.0e4, 500.)
+        };
+        commands
+            .entity(*wheel_id)
+            .remove::<BrakeWheel>()
+            .insert(brake.with_hydraulics(hydraulics_id));
+    }
+
+    // abs, traction control and esc, only on the full car
+    commands.spawn(
+        DriverAssist::new(
+            car.chassis_id,
+            [
+                car.wheel_ids[0],
+                car.wheel_ids[1],
+                car.wheel_ids[2],
+                car.wheel_ids[3],
+            ],
+            car.steering_ids.clone(),
+            0.325,
+        )
+        .with_abs(Abs::new(0.15, 8., 4., 2.))
+        .with_traction_control(TractionControl::new(0.15, 5., 2.))
+        .with_esc(Esc::new(2.5, 0.0025, 1., 2000., 0.05, 1500., 5.)),
+    );
+
+    // rolling resistance, a warmed up tread that wears slowly, and loads and forces for telemetry
+    for wheel_id in car.wheel_ids.iter() {
+        commands.entity(*wheel_id).insert((
+            TireCondition::new(0.01, 5.0e-6)
+                .with_heating(3600., 20., 2., 0.5, 25.)
+                .with_thermal_grip(60., 60., 0.2)
+                .with_wear(1.0e-8, 0.1)
+                .with_initial_temperature(60.),
+            TireState::default(),
+        ));
+    }
+
+    // body aerodynamics, a little downforce biased to the rear, and a collider that keeps the
+    // body from falling through the ground if the car rolls over
+    let dimensions = [3.0_f32, 1.5, 0.4];
+    commands.entity(car.chassis_id).insert((
+        Aero::new(0.35, -0.2, 1.0, 2.2, Vector::new(-0.1, 0., 0.)).with_lift_split(LiftSplit::new(
+            0.4,
+            Vector::new(1.25, 0., -0.3),
+            Vector::new(-1.25, 0., -0.3),
+        )),
+        chassis_collider(dimensions),
+    ));
+
+    // a stack of crates in front of the car, to push around
+    for (ind, height) in [0.25, 0.75, 1.25].iter().enumerate() {
+        build_crate(
+            commands,
+            meshes,
+            materials,
+            car.base_id,
+            ind,
+            [8., 0., *height],
+        );
+    }
+}
+
+// a free 0.5 m crate, six joints from the base like the chassis
+fn build_crate(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    base_id: Entity,
+    ind: usize,
+    position: [f32; 3],
+) {
+    let size = 0.5;
+    let mass = 20.;
+    let name = |dof: &str| format!("crate_{}_{}", ind, dof);
+
+    // translations in absolute coordinates, then yaw, pitch and roll
+    let mut parent_id = base_id;
+    for (mut joint, q) in [
+        (
+            Joint::px(name("px"), Inertia::zero(), Xform::identity()),
+            position[0],
+        ),
+        (
+            Joint::py(name("py"), Inertia::zero(), Xform::identity()),
+            position[1],
+        ),
+        (
+            Joint::pz(name("pz"), Inertia::zero(), Xform::identity()),
+            position[2],
+        ),
+        (
+            Joint::rz(name("rz"), Inertia::zero(), Xform::identity()),
+            0.,
+        ),
+        (
+            Joint::ry(name("ry"), Inertia::zero(), Xform::identity()),
+            0.,
+        ),
+    ] {
+        joint.q = q;
+        let mut joint_e = commands.spawn((joint, SpatialBundle::default()));
+        joint_e.set_parent(parent_id);
+        parent_id = joint_e.id();
     }
+
+    let inertia = Inertia::new(
+        mass,
+        Vector::zeros(),
+        mass * size * size / 6. * Matrix::identity(),
+    );
+    let rx = Joint::rx(name("rx"), inertia, Xform::identity());
+    let mut rx_e = commands.spawn((rx, SpatialBundle::default()));
+    rx_e.set_parent(parent_id);
+    add_cube_mesh(
+        &mut rx_e,
+        meshes,
+        materials,
+        [size, size, size],
+        Color::rgb(0.6, 0.4, 0.2),
+    );
+
+    // the spring and damper are only used by the penalty contacts, without the contact solver
+    let half = size / 2.;
+    let shape = CollisionShape::new(
+        RBDA_Mesh::Box(BoxMesh::new(-half, half, -half, half, -half, half)),
+        Xform::identity(),
+    );
+    let stiffness = mass * 9.81 / 4. / 0.002;
+    let damping = 2. * (mass / 4. * stiffness).sqrt();
+    rx_e.insert(Collider::new(vec![shape], stiffness, damping, 0.6, 0));
 }
 
 // build the chassis from a series of joints
@@ -234,15 +496,9 @@ fn build_wheel(
     add_tire_contact(&mut wheel_e);
 
     if driven {
-        wheel_e.insert(DrivenWheel {
-            max_torque: 400.,
-            max_speed: 100.,
-            max_power: 100.0e3,
-        });
+        wheel_e.insert(DrivenWheel::new(400., 100., 100.0e3));
     }
-    wheel_e.insert(BrakeWheel {
-        max_torque: if driven { 800. } else { 400. },
-    });
+    wheel_e.insert(BrakeWheel::new(if driven { 800. } else { 400. }));
 
     wheel_e.set_parent(parent_id);
     let wheel_id = wheel_e.id();
@@ -256,6 +512,23 @@ fn add_tire_contact(entity: &mut EntityCommands) {
     entity.insert(TireContact::new(0.325, stiffness, damping, 0.2, 0.5));
 }
 
+fn chassis_collider(dimensions: [f32; 3]) -> Collider {
+    let shape = CollisionShape::new(
+        RBDA_Mesh::Box(BoxMesh::new(
+            -dimensions[0] / 2.,
+            dimensions[0] / 2.,
+            -dimensions[1] / 2.,
+            dimensions[1] / 2.,
+            -dimensions[2] / 2.,
+            dimensions[2] / 2.,
+        )),
+        Xform::identity(),
+    );
+    let stiffness = 1000. * 9.81 / 4. / 0.01;
+    let damping = 0.5 * 2. * (1000.0_f32 / 4. * stiffness).sqrt();
+    Collider::new(vec![shape], stiffness, damping, 0.8, 1)
+}
+
 fn add_cube_mesh(
     entity: &mut EntityCommands,
     meshes: &mut ResMut<Assets<Mesh>>,
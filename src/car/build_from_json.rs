@@ -1,10 +1,24 @@
 use std::{collections::HashMap, fs::File, io::Read};
 
-use super::physics::{BrakeWheel, DrivenWheel, Steering, Suspension, TireContact};
+use super::{
+    aero::Aero,
+    assist::DriverAssist,
+    brakes::{Brake, BrakeHydraulics},
+    differential::{Differential, DriveOutput},
+    electric::ElectricDrive,
+    pacejka::PacejkaTire,
+    physics::{
+        AntiRollBar, BrakeWheel, DrivenWheel, Steering, SteeringRack, Suspension,
+        SuspensionKinematics, TableTire, TireCondition, TireContact, TireRelaxation, TireState,
+    },
+    powertrain::Powertrain,
+};
 use crate::{
+    collision::Collider,
     joint::{Base, Joint},
     serialize::{JointTypeDef, MeshDef, MeshTypeDef, ModelDef, SystemTypeDef},
     sva::Motion,
+    wind::Wind,
 };
 use bevy::prelude::*;
 
@@ -27,6 +41,9 @@ pub fn build_model(
 
         let joint_ids = build_joints(commands, meshes, materials, &model);
         build_systems(commands, &model, &joint_ids);
+        if let Some(wind_def) = &model.wind {
+            commands.insert_resource(Wind::from_def(wind_def));
+        }
     }
 }
 
@@ -76,7 +93,80 @@ pub fn build_joints(
     joint_ids
 }
 
+// differentials feeding each other in a loop would make the torque and speed recursion in the
+// differential systems never end
+fn check_differential_cycles(model: &ModelDef) {
+    let outputs: HashMap<&String, &[String; 2]> = model
+        .systems
+        .iter()
+        .filter_map(|system_def| match &system_def.system_type {
+            SystemTypeDef::Differential(differential_def) => {
+                Some((&differential_def.name, &differential_def.outputs))
+            }
+            _ => None,
+        })
+        .collect();
+
+    // depth first from each differential, path holds the differentials above the current one
+    fn visit<'a>(
+        name: &'a String,
+        outputs: &HashMap<&String, &'a [String; 2]>,
+        path: &mut Vec<&'a String>,
+    ) {
+        if path.contains(&name) {
+            panic!(
+                "Differential cycle: {} -> {}",
+                path.iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" -> "),
+                name
+            );
+        }
+        let Some(differential_outputs) = outputs.get(name) else {
+            return; // a wheel
+        };
+        path.push(name);
+        for output in differential_outputs.iter() {
+            visit(output, outputs, path);
+        }
+        path.pop();
+    }
+    for name in outputs.keys() {
+        visit(name, &outputs, &mut Vec::new());
+    }
+}
+
 fn build_systems(commands: &mut Commands, model: &ModelDef, joint_ids: &HashMap<String, Entity>) {
+    // differentials are referenced by name, so they get entities before anything is built
+    let differential_ids: HashMap<String, Entity> = model
+        .systems
+        .iter()
+        .filter_map(|system_def| match &system_def.system_type {
+            SystemTypeDef::Differential(differential_def) => {
+                Some((differential_def.name.clone(), commands.spawn_empty().id()))
+            }
+            _ => None,
+        })
+        .collect();
+    // disc brakes reference their hydraulics by name as well
+    let brake_hydraulics_ids: HashMap<String, Entity> = model
+        .systems
+        .iter()
+        .filter_map(|system_def| match &system_def.system_type {
+            SystemTypeDef::BrakeHydraulics(brake_hydraulics_def) => Some((
+                brake_hydraulics_def.name.clone(),
+                commands.spawn_empty().id(),
+            )),
+            _ => None,
+        })
+        .collect();
+    check_differential_cycles(model);
+    let drive_output = |name: &String| match differential_ids.get(name) {
+        Some(differential_id) => DriveOutput::Differential(*differential_id),
+        None => DriveOutput::Wheel(*joint_ids.get(name).unwrap()),
+    };
+
     for system_def in model.systems.iter() {
         match &system_def.system_type {
             SystemTypeDef::Steering(steering_def) => {
@@ -85,28 +175,186 @@ fn build_systems(commands: &mut Commands, model: &ModelDef, joint_ids: &HashMap<
                     .entity(*steering_id)
                     .insert(Steering::from_def(&steering_def));
             }
+            SystemTypeDef::SteeringRack(steering_rack_def) => {
+                let left_id = joint_ids.get(&steering_rack_def.left).unwrap();
+                let right_id = joint_ids.get(&steering_rack_def.right).unwrap();
+                commands.spawn(SteeringRack::from_def(
+                    steering_rack_def,
+                    *left_id,
+                    *right_id,
+                ));
+            }
             SystemTypeDef::Drive(drive_def) => {
                 let drive_id = joint_ids.get(&drive_def.joint).unwrap();
                 let mut drive_e = commands.entity(*drive_id);
                 drive_e.insert(DrivenWheel::from_def(&drive_def));
             }
+            SystemTypeDef::Powertrain(powertrain_def) => {
+                let engine_id = joint_ids.get(&powertrain_def.engine_joint).unwrap();
+                let wheel_ids = powertrain_def
+                    .wheels
+                    .iter()
+                    .map(|wheel| *joint_ids.get(wheel).unwrap())
+                    .collect();
+                let mut powertrain = Powertrain::from_def(powertrain_def, *engine_id, wheel_ids)
+                    .unwrap_or_else(|e| {
+                        panic!("powertrain {}: {}", powertrain_def.engine_joint, e)
+                    });
+                if let Some(differential) = &powertrain_def.differential {
+                    powertrain =
+                        powertrain.with_differential(*differential_ids.get(differential).unwrap());
+                }
+                commands.spawn(powertrain);
+            }
+            SystemTypeDef::ElectricDrive(electric_drive_def) => {
+                let wheel_ids = electric_drive_def
+                    .wheels
+                    .iter()
+                    .map(|wheel| *joint_ids.get(wheel).unwrap())
+                    .collect();
+                let mut electric_drive = ElectricDrive::from_def(electric_drive_def, wheel_ids)
+                    .unwrap_or_else(|e| panic!("electric drive: {}", e));
+                if let Some(differential) = &electric_drive_def.differential {
+                    electric_drive = electric_drive
+                        .with_differential(*differential_ids.get(differential).unwrap());
+                }
+                commands.spawn(electric_drive);
+            }
+            SystemTypeDef::Differential(differential_def) => {
+                let outputs = differential_def
+                    .outputs
+                    .clone()
+                    .map(|output| drive_output(&output));
+                commands
+                    .entity(*differential_ids.get(&differential_def.name).unwrap())
+                    .insert(Differential::from_def(differential_def, outputs));
+            }
             SystemTypeDef::Brake(brake_def) => {
                 let brake_id = joint_ids.get(&brake_def.joint).unwrap();
                 commands
                     .entity(*brake_id)
                     .insert(BrakeWheel::from_def(&brake_def));
             }
+            SystemTypeDef::BrakeHydraulics(brake_hydraulics_def) => {
+                let brake_hydraulics = BrakeHydraulics::from_def(brake_hydraulics_def)
+                    .unwrap_or_else(|e| panic!("brake hydraulics: {}", e));
+                commands
+                    .entity(brake_hydraulics_ids[&brake_hydraulics_def.name])
+                    .insert(brake_hydraulics);
+            }
+            SystemTypeDef::DiscBrake(brake_def) => {
+                let brake_id = joint_ids.get(&brake_def.joint).unwrap();
+                let hydraulics_id = brake_hydraulics_ids
+                    .get(&brake_def.hydraulics)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "brake {}: no brake hydraulics {}",
+                            brake_def.joint, brake_def.hydraulics
+                        )
+                    });
+                commands
+                    .entity(*brake_id)
+                    .insert(Brake::from_def(brake_def).with_hydraulics(*hydraulics_id));
+            }
             SystemTypeDef::Suspension(suspension_def) => {
                 let suspension_id = joint_ids.get(&suspension_def.joint).unwrap();
-                commands
-                    .entity(*suspension_id)
-                    .insert(Suspension::from_def(&suspension_def));
+                let suspension = Suspension::from_def(suspension_def)
+                    .unwrap_or_else(|e| panic!("suspension {}: {}", suspension_def.joint, e));
+                commands.entity(*suspension_id).insert(suspension);
+            }
+            SystemTypeDef::AntiRollBar(anti_roll_bar_def) => {
+                let left_id = joint_ids.get(&anti_roll_bar_def.left).unwrap();
+                let right_id = joint_ids.get(&anti_roll_bar_def.right).unwrap();
+                commands.spawn(AntiRollBar::from_def(
+                    anti_roll_bar_def,
+                    *left_id,
+                    *right_id,
+                ));
+            }
+            SystemTypeDef::SuspensionKinematics(suspension_kinematics_def) => {
+                let wheel_id = joint_ids.get(&suspension_kinematics_def.joint).unwrap();
+                let suspension_id = joint_ids
+                    .get(&suspension_kinematics_def.suspension)
+                    .unwrap();
+                let steering_id = suspension_kinematics_def
+                    .steering
+                    .as_ref()
+                    .map(|steering| *joint_ids.get(steering).unwrap());
+                let suspension_kinematics = SuspensionKinematics::from_def(
+                    suspension_kinematics_def,
+                    *suspension_id,
+                    steering_id,
+                )
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "suspension kinematics {}: {}",
+                        suspension_kinematics_def.joint, e
+                    )
+                });
+                commands.entity(*wheel_id).insert(suspension_kinematics);
             }
             SystemTypeDef::TireContact(tire_contact_def) => {
                 let tire_contact_id = joint_ids.get(&tire_contact_def.joint).unwrap();
+                commands.entity(*tire_contact_id).insert((
+                    TireContact::from_def(tire_contact_def),
+                    TireState::default(),
+                ));
+            }
+            SystemTypeDef::TireRelaxation(tire_relaxation_def) => {
+                let tire_relaxation_id = joint_ids.get(&tire_relaxation_def.joint).unwrap();
+                commands
+                    .entity(*tire_relaxation_id)
+                    .insert(TireRelaxation::from_def(tire_relaxation_def));
+            }
+            SystemTypeDef::TireCondition(tire_condition_def) => {
+                let tire_condition_id = joint_ids.get(&tire_condition_def.joint).unwrap();
                 commands
-                    .entity(*tire_contact_id)
-                    .insert(TireContact::from_def(&tire_contact_def));
+                    .entity(*tire_condition_id)
+                    .insert(TireCondition::from_def(tire_condition_def));
+            }
+            SystemTypeDef::PacejkaTire(pacejka_tire_def) => {
+                let pacejka_tire_id = joint_ids.get(&pacejka_tire_def.joint).unwrap();
+                let pacejka_tire = PacejkaTire::from_def(pacejka_tire_def)
+                    .unwrap_or_else(|e| panic!("pacejka tire {}: {}", pacejka_tire_def.joint, e));
+                commands
+                    .entity(*pacejka_tire_id)
+                    .insert((pacejka_tire, TireState::default()));
+            }
+            SystemTypeDef::TableTire(table_tire_def) => {
+                let table_tire_id = joint_ids.get(&table_tire_def.joint).unwrap();
+                let table_tire = TableTire::from_def(table_tire_def)
+                    .unwrap_or_else(|e| panic!("table tire {}: {}", table_tire_def.joint, e));
+                commands
+                    .entity(*table_tire_id)
+                    .insert((table_tire, TireState::default()));
+            }
+            SystemTypeDef::Aero(aero_def) => {
+                let aero_id = joint_ids.get(&aero_def.joint).unwrap();
+                commands.entity(*aero_id).insert(Aero::from_def(aero_def));
+            }
+            SystemTypeDef::DriverAssist(driver_assist_def) => {
+                let chassis_id = joint_ids.get(&driver_assist_def.chassis).unwrap();
+                let wheel_ids = driver_assist_def
+                    .wheels
+                    .clone()
+                    .map(|wheel| *joint_ids.get(&wheel).unwrap());
+                let steering_ids = driver_assist_def
+                    .steering
+                    .iter()
+                    .map(|steering| *joint_ids.get(steering).unwrap())
+                    .collect();
+                commands.spawn(DriverAssist::from_def(
+                    driver_assist_def,
+                    *chassis_id,
+                    wheel_ids,
+                    steering_ids,
+                ));
+            }
+            SystemTypeDef::Collider(collider_def) => {
+                let collider_id = joint_ids.get(&collider_def.joint).unwrap();
+                commands
+                    .entity(*collider_id)
+                    .insert(Collider::from_def(collider_def));
             }
         }
     }
@@ -133,7 +381,15 @@ fn build_mesh(
             radius: *radius,
             ..Default::default()
         }),
-        // MeshTypeDef::Sphere { radius } => todo!(),
+        MeshTypeDef::Sphere { radius } => Mesh::from(shape::UVSphere {
+            radius: *radius,
+            ..Default::default()
+        }),
+        MeshTypeDef::Capsule { length, radius } => Mesh::from(shape::Capsule {
+            depth: *length,
+            radius: *radius,
+            ..Default::default()
+        }),
         // MeshTypeDef::Mesh { filename } => todo!(),
     };
     let color = Color::rgb(mesh_def.color[0], mesh_def.color[1], mesh_def.color[2]);
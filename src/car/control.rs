@@ -1,10 +1,30 @@
 use bevy::prelude::*;
 
-#[derive(Resource, Default)]
+use crate::simulation::{SimulationControl, SimulationEvent};
+
+use super::powertrain::Powertrain;
+
+#[derive(Resource)]
 pub struct CarControl {
     pub throttle: f32,
     pub steering: f32,
     pub brake: f32,
+    pub clutch: f32, // pedal, 0 engaged to 1 disengaged
+    pub handbrake: f32,
+    pub gear: i32, // -1 reverse, 0 neutral, 1.. forward (drive for an automatic)
+}
+
+impl Default for CarControl {
+    fn default() -> Self {
+        Self {
+            throttle: 0.,
+            steering: 0.,
+            brake: 0.,
+            clutch: 0.,
+            handbrake: 0.,
+            gear: 1,
+        }
+    }
 }
 
 pub fn user_control_system(
@@ -13,6 +33,7 @@ pub fn user_control_system(
     button_axes: Res<Axis<GamepadButton>>,
     axes: Res<Axis<GamepadAxis>>,
     mut control: ResMut<CarControl>,
+    powertrains: Query<&Powertrain>,
 ) {
     // gamepad controls
     for gamepad in gamepads.iter() {
@@ -55,6 +76,33 @@ pub fn user_control_system(
         control.brake = control.brake.max(0.0);
     }
 
+    if keyboard_input.pressed(KeyCode::C) {
+        control.clutch += time_constant;
+        control.clutch = control.clutch.min(1.0);
+    } else {
+        control.clutch -= time_constant;
+        control.clutch = control.clutch.max(0.0);
+    }
+
+    // space: handbrake
+    control.handbrake = if keyboard_input.pressed(KeyCode::Space) {
+        1.
+    } else {
+        0.
+    };
+
+    // E/Q: shift up/down
+    if keyboard_input.just_pressed(KeyCode::E) {
+        control.gear += 1;
+        // not past the top gear of the gearbox
+        if let Some(max_gear) = powertrains.iter().map(|p| p.max_gear()).max() {
+            control.gear = control.gear.min(max_gear);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Q) {
+        control.gear = (control.gear - 1).max(-1);
+    }
+
     let mut steer_active = false;
     if keyboard_input.pressed(KeyCode::A) {
         control.steering += time_constant;
@@ -78,3 +126,23 @@ pub fn user_control_system(
         }
     }
 }
+
+// P: pause/resume, N: single step, =/-: double/halve the simulation speed
+pub fn simulation_control_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    simulation: Res<SimulationControl>,
+    mut events: EventWriter<SimulationEvent>,
+) {
+    if keyboard_input.just_pressed(KeyCode::P) {
+        events.send(SimulationEvent::TogglePause);
+    }
+    if keyboard_input.just_pressed(KeyCode::N) {
+        events.send(SimulationEvent::Step(1));
+    }
+    if keyboard_input.just_pressed(KeyCode::Equals) {
+        events.send(SimulationEvent::SetTimeScale(simulation.time_scale * 2.));
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        events.send(SimulationEvent::SetTimeScale(simulation.time_scale / 2.));
+    }
+}
@@ -2,8 +2,12 @@ use std::fs::File;
 use std::{f32::consts::PI, io::Write};
 
 use crate::serialize::{
-    BrakeWheelDef, DrivenWheelDef, InertiaDef, JointDef, JointTypeDef, MeshDef, MeshTypeDef,
-    ModelDef, SteeringDef, SuspensionDef, SystemDef, SystemTypeDef, TireContactDef, TransformDef,
+    AbsDef, AeroDef, AntiRollBarDef, BrakeCircuitDef, BrakeDef, BrakeHydraulicsDef, ColliderDef,
+    CollisionShapeDef, DifferentialDef, DifferentialTypeDef, DriverAssistDef, EscDef, InertiaDef,
+    JointDef, JointTypeDef, KinematicCurveDef, LiftSplitDef, MeshDef, MeshTypeDef, ModelDef,
+    PowertrainDef, ShiftModeDef, SteeringRackDef, SuspensionDef, SuspensionKinematicsDef,
+    SystemDef, SystemTypeDef, TireConditionDef, TireContactDef, TractionControlDef, TransformDef,
+    TravelStopDef,
 };
 
 const ZERO_INERTIA: InertiaDef = InertiaDef {
@@ -35,16 +39,24 @@ pub fn car_json() {
     joints.push(base_joint);
 
     // create joints and systems
-    let chassis_name = chassis_joints(&mut joints);
-    suspension_joints(&mut joints, &mut systems, chassis_name, &corner_names);
+    let chassis_name = chassis_joints(&mut joints, &mut systems);
+    suspension_joints(
+        &mut joints,
+        &mut systems,
+        chassis_name.clone(),
+        &corner_names,
+    );
     steering_joints(&mut joints, &mut systems, &corner_names);
     wheel_joints(&mut joints, &mut systems, &corner_names);
+    powertrain_joints(&mut joints, &mut systems, chassis_name.clone());
+    driver_assist(&mut systems, chassis_name, &corner_names);
 
     // define the model
     let model = ModelDef {
         name: "car".to_string(),
         joints,
         systems,
+        wind: None,
     };
 
     // serialize the model to json
@@ -63,7 +75,7 @@ pub fn car_json() {
     file.write_all(json.as_bytes()).unwrap();
 }
 
-fn chassis_joints(joints: &mut Vec<JointDef>) -> String {
+fn chassis_joints(joints: &mut Vec<JointDef>, systems: &mut Vec<SystemDef>) -> String {
     // define chassis joints - 6 dof (Px, Py, Pz,Rx, Ry, Rz)
     let chassis_joint_def = vec![
         JointTypeDef::Px,
@@ -119,6 +131,47 @@ fn chassis_joints(joints: &mut Vec<JointDef>) -> String {
         joints.push(chassis);
         parent_name = name;
     }
+
+    // body aerodynamics, a little downforce biased to the rear
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::Aero(AeroDef {
+            joint: parent_name.clone(),
+            drag_coefficient: 0.35,
+            lift_coefficient: -0.2,
+            side_coefficient: 1.0,
+            area: 2.2,
+            air_density: 1.225,
+            centre_of_pressure: [-0.1, 0., 0.],
+            lift_split: Some(LiftSplitDef {
+                front_fraction: 0.4,
+                front_point: [1.25, 0., -0.3],
+                rear_point: [-1.25, 0., -0.3],
+            }),
+        }),
+    });
+
+    // collide the chassis body with the ground (the car is group 1, so it can't hit itself)
+    let collider_stiffness = chassis_mass * 9.81 / 4. / 0.01;
+    let collider_damping = 0.5 * 2. * (chassis_mass / 4. * collider_stiffness).sqrt();
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::Collider(ColliderDef {
+            joint: parent_name.clone(),
+            shapes: vec![CollisionShapeDef {
+                shape: MeshTypeDef::Box {
+                    half_extents: [
+                        chassis_dims[0] / 2.,
+                        chassis_dims[1] / 2.,
+                        chassis_dims[2] / 2.,
+                    ],
+                },
+                transform: ZERO_TRANSFORM,
+            }],
+            stiffness: collider_stiffness,
+            damping: collider_damping,
+            friction: 0.8,
+            group: 1,
+        }),
+    });
     parent_name
 }
 
@@ -168,6 +221,34 @@ fn suspension_joints(
                 joint: name,
                 stiffness,
                 damping,
+                spring_curve: None,
+                // the preload carries the static load, so ride height is the static travel
+                preload: 1000. * 9.81 / 4.,
+                ride_height: 0.1,
+                // digressive damper, stiffer in rebound
+                bump_curve: Some(vec![[0., 0.], [0.1, 200.], [0.5, 600.], [2., 1500.]]),
+                rebound_curve: Some(vec![[0., 0.], [0.1, 350.], [0.5, 1100.], [2., 2800.]]),
+                bump_stop: Some(TravelStopDef {
+                    position: 0.15,
+                    stiffness: 5.0e6,
+                    exponent: 2.,
+                }),
+                rebound_stop: Some(TravelStopDef {
+                    position: -0.05,
+                    stiffness: 5.0e6,
+                    exponent: 2.,
+                }),
+            }),
+        });
+    }
+
+    // anti-roll bars, stiffer at the front for understeer
+    for (axle, bar_stiffness) in [(0, 0.6 * stiffness), (2, 0.3 * stiffness)] {
+        systems.push(SystemDef {
+            system_type: SystemTypeDef::AntiRollBar(AntiRollBarDef {
+                left: format!("suspension_{}", corner_names[axle]),
+                right: format!("suspension_{}", corner_names[axle + 1]),
+                stiffness: bar_stiffness,
             }),
         });
     }
@@ -197,13 +278,26 @@ fn steering_joints(
             meshes: vec![],
         };
         joints.push(steering);
-        systems.push(SystemDef {
-            system_type: SystemTypeDef::Steering(SteeringDef {
-                joint: name,
-                max_angle: 30. * PI / 180.,
-            }),
-        });
     }
+
+    // steering rack for the front wheels, 30 degrees at full lock
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::SteeringRack(SteeringRackDef {
+            left: format!("steering_{}", corner_names[0]),
+            right: format!("steering_{}", corner_names[1]),
+            steering_ratio: 15.,
+            max_handwheel_angle: 450. * PI / 180.,
+            arm_length: 0.15,
+            max_rack_travel: 0.15 * (30. * PI / 180.).sin(),
+            wheelbase: 2.5,
+            track: 1.5,
+            ackermann: 1.,
+            toe_left: 0.,
+            toe_right: 0.,
+            column_stiffness: None,
+            column_damping: 0.,
+        }),
+    });
 }
 
 fn wheel_joints(
@@ -247,23 +341,56 @@ fn wheel_joints(
         };
         joints.push(wheel);
 
-        // drive system for rear wheels
-        if i >= 2 {
-            systems.push(SystemDef {
-                system_type: SystemTypeDef::Drive(DrivenWheelDef {
-                    joint: name.clone(),
-                    max_torque: 400.,
-                    max_speed: 50.,
-                    max_power: 100.0e3,
+        // camber gain and bump steer, with steer camber from caster at the front
+        let left = i % 2 == 0;
+        let side = if left { 1. } else { -1. };
+        systems.push(SystemDef {
+            system_type: SystemTypeDef::SuspensionKinematics(SuspensionKinematicsDef {
+                joint: name.clone(),
+                suspension: format!("suspension_{}", corner_names[i]),
+                steering: (i < 2).then(|| format!("steering_{}", corner_names[i])),
+                left,
+                camber: Some(KinematicCurveDef::Polynomial {
+                    travel: vec![0., if i < 2 { -0.5 } else { -0.3 }],
+                    steer: if i < 2 { vec![0.1 * side] } else { vec![] },
                 }),
-            });
-        }
+                toe: Some(KinematicCurveDef::Polynomial {
+                    travel: vec![0., if i < 2 { -0.05 } else { 0.1 }],
+                    steer: vec![],
+                }),
+                track: Some(KinematicCurveDef::Polynomial {
+                    travel: vec![0., 0., -0.5],
+                    steer: vec![],
+                }),
+            }),
+        });
 
-        // brake system for all wheels
+        // disc brakes for all wheels, handbrake on the rear
         systems.push(SystemDef {
-            system_type: SystemTypeDef::Brake(BrakeWheelDef {
-                joint: name.clone(),
-                max_torque: if i < 2 { 800. } else { 400. },
+            system_type: SystemTypeDef::DiscBrake(if i < 2 {
+                BrakeDef {
+                    joint: name.clone(),
+                    hydraulics: "brake_hydraulics".to_string(),
+                    circuit: BrakeCircuitDef::Front,
+                    pad_friction: 0.4,
+                    piston_area: 7.7e-4,
+                    effective_radius: 0.13,
+                    handbrake_torque: 0.,
+                    hold_stiffness: 1.0e4,
+                    hold_damping: 500.,
+                }
+            } else {
+                BrakeDef {
+                    joint: name.clone(),
+                    hydraulics: "brake_hydraulics".to_string(),
+                    circuit: BrakeCircuitDef::Rear,
+                    pad_friction: 0.4,
+                    piston_area: 6.0e-4,
+                    effective_radius: 0.12,
+                    handbrake_torque: 600.,
+                    hold_stiffness: 1.0e4,
+                    hold_damping: 500.,
+                }
             }),
         });
 
@@ -278,7 +405,142 @@ fn wheel_joints(
                 damping: tire_damping,
                 longitudinal_stiffness: 0.2,
                 lateral_stiffness: 0.5,
+                width: 0.,
+                num_points: 1,
+                profile: 0.,
+            }),
+        });
+
+        // rolling resistance, tread temperature and wear for all wheels
+        systems.push(SystemDef {
+            system_type: SystemTypeDef::TireCondition(TireConditionDef {
+                joint: name.clone(),
+                rolling_resistance: 0.01,
+                rolling_resistance_speed: 5.0e-6,
+                heat_capacity: 3600.,
+                cooling: 20.,
+                cooling_speed: 2.,
+                heating_fraction: 0.5,
+                ambient_temperature: 25.,
+                optimal_temperature: 60.,
+                temperature_window: 60.,
+                thermal_grip_loss: 0.2,
+                wear_rate: 1.0e-8,
+                worn_grip_loss: 0.1,
+                initial_temperature: Some(60.), // warmed up
             }),
         });
     }
 }
+
+fn powertrain_joints(
+    joints: &mut Vec<JointDef>,
+    systems: &mut Vec<SystemDef>,
+    chassis_name: String,
+) {
+    // engine crank and flywheel, spinning about the car's x axis
+    let name = "engine".to_string();
+    joints.push(JointDef {
+        name: name.clone(),
+        joint_type: JointTypeDef::Rx,
+        parent: Some(chassis_name),
+        transform: TransformDef {
+            position: [1., 0., 0.],
+            quaternion: [1., 0., 0., 0.],
+        },
+        inertia: InertiaDef {
+            mass: 0.,
+            center_of_mass: [0., 0., 0.],
+            inertia: [0.2, 0., 0., 0., 0., 0.],
+        },
+        meshes: vec![],
+    });
+
+    // 100 bar at full pedal after a little dead travel, 60% front bias
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::BrakeHydraulics(BrakeHydraulicsDef {
+            name: "brake_hydraulics".to_string(),
+            pedal_pressure: vec![[0., 0.], [0.05, 0.], [1., 1.0e7]],
+            bias: 0.6,
+            time_constant: 0.05,
+        }),
+    });
+
+    // rear wheel drive through a five speed manual gearbox and a clutch pack lsd
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::Differential(DifferentialDef {
+            name: "rear_differential".to_string(),
+            outputs: ["wheel_rl".to_string(), "wheel_rr".to_string()],
+            torque_split: 0.5,
+            differential_type: DifferentialTypeDef::ClutchPack {
+                preload: 50.,
+                power_ramp_angle: 45. * PI / 180.,
+                coast_ramp_angle: 60. * PI / 180.,
+                friction_factor: 0.5,
+                damping: 100.,
+            },
+        }),
+    });
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::Powertrain(PowertrainDef {
+            engine_joint: name,
+            wheels: vec!["wheel_rl".to_string(), "wheel_rr".to_string()],
+            differential: Some("rear_differential".to_string()),
+            torque_curve: vec![
+                [0., 100.],
+                [1000., 180.],
+                [2500., 250.],
+                [4500., 280.],
+                [6000., 250.],
+                [7000., 200.],
+            ],
+            idle_rpm: 800.,
+            max_rpm: 6800.,
+            friction: [10., 0.02],
+            clutch_capacity: 400.,
+            clutch_damping: 100.,
+            gear_ratios: vec![3.5, 2.1, 1.4, 1.0, 0.8],
+            reverse_ratio: 3.2,
+            final_drive: 3.7,
+            shift_mode: ShiftModeDef::Manual,
+            shift_time: 0.2,
+        }),
+    });
+}
+
+fn driver_assist(systems: &mut Vec<SystemDef>, chassis_name: String, corner_names: &[&str]) {
+    // abs, traction control and esc, off until enabled in the json
+    systems.push(SystemDef {
+        system_type: SystemTypeDef::DriverAssist(DriverAssistDef {
+            chassis: chassis_name,
+            wheels: [0, 1, 2, 3].map(|i| format!("wheel_{}", corner_names[i])),
+            steering: (0..2)
+                .map(|i| format!("steering_{}", corner_names[i]))
+                .collect(),
+            wheel_radius: 0.325,
+            abs: Some(AbsDef {
+                enabled: false,
+                target_slip: 0.15,
+                release_rate: 8.,
+                apply_rate: 4.,
+                min_speed: 2.,
+            }),
+            traction_control: Some(TractionControlDef {
+                enabled: false,
+                target_slip: 0.15,
+                cut_rate: 5.,
+                recover_rate: 2.,
+            }),
+            esc: Some(EscDef {
+                enabled: false,
+                wheelbase: 2.5,
+                understeer_gradient: 0.0025,
+                friction: 1.,
+                gain: 2000.,
+                deadband: 0.05,
+                max_torque: 1500.,
+                min_speed: 5.,
+            }),
+        }),
+    });
+}
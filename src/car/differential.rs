@@ -0,0 +1,343 @@
+use bevy::prelude::*;
+
+use crate::{
+    joint::Joint,
+    serialize::{DifferentialDef, DifferentialTypeDef},
+};
+
+// where drive torque goes: straight into a wheel joint, or into another differential
+#[derive(Debug, Clone, Copy)]
+pub enum DriveOutput {
+    Wheel(Entity),
+    Differential(Entity),
+}
+
+impl DriveOutput {
+    // speed seen by whatever drives this output
+    pub fn speed(
+        &self,
+        differentials: &Query<&mut Differential>,
+        joints: &Query<&mut Joint>,
+    ) -> f32 {
+        match self {
+            DriveOutput::Wheel(wheel) => joints.get(*wheel).map_or(0., |joint| joint.qd),
+            DriveOutput::Differential(differential) => {
+                differentials.get(*differential).map_or(0., |differential| {
+                    differential.input_speed(differentials, joints)
+                })
+            }
+        }
+    }
+
+    // wheels at the end of this output, through any further differentials
+    pub fn wheels(&self, differentials: &Query<&Differential>) -> Vec<Entity> {
+        match self {
+            DriveOutput::Wheel(wheel) => vec![*wheel],
+            DriveOutput::Differential(differential) => {
+                differentials
+                    .get(*differential)
+                    .map_or(Vec::new(), |differential| {
+                        differential
+                            .outputs
+                            .iter()
+                            .flat_map(|output| output.wheels(differentials))
+                            .collect()
+                    })
+            }
+        }
+    }
+
+    pub fn apply_torque(
+        &self,
+        torque: f32,
+        differentials: &mut Query<&mut Differential>,
+        joints: &mut Query<&mut Joint>,
+    ) {
+        match self {
+            DriveOutput::Wheel(wheel) => {
+                if let Ok(mut joint) = joints.get_mut(*wheel) {
+                    joint.tau += torque;
+                }
+            }
+            DriveOutput::Differential(differential) => {
+                Differential::drive(*differential, torque, differentials, joints)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DifferentialType {
+    Open,
+    Locked {
+        damping: f32, // Nm per rad/s, stiff enough to hold the outputs together
+    },
+    Viscous {
+        coefficient: f32, // Nm per rad/s of speed difference
+    },
+    // clutch pack lsd: the ramps push the clutches together in proportion to the input torque
+    ClutchPack {
+        preload: f32,          // Nm of locking torque with no input torque
+        power_ramp_angle: f32, // rad, from the ramp axis, steeper locks less
+        coast_ramp_angle: f32,
+        friction_factor: f32, // clutch faces * friction * mean radius / ramp radius
+        damping: f32,         // Nm per rad/s of slip, until the locking torque is reached
+    },
+}
+
+impl DifferentialType {
+    pub fn from_def(differential_type_def: &DifferentialTypeDef) -> Self {
+        match differential_type_def {
+            DifferentialTypeDef::Open => DifferentialType::Open,
+            DifferentialTypeDef::Locked { damping } => {
+                DifferentialType::Locked { damping: *damping }
+            }
+            DifferentialTypeDef::Viscous { coefficient } => DifferentialType::Viscous {
+                coefficient: *coefficient,
+            },
+            DifferentialTypeDef::ClutchPack {
+                preload,
+                power_ramp_angle,
+                coast_ramp_angle,
+                friction_factor,
+                damping,
+            } => DifferentialType::ClutchPack {
+                preload: *preload,
+                power_ramp_angle: *power_ramp_angle,
+                coast_ramp_angle: *coast_ramp_angle,
+                friction_factor: *friction_factor,
+                damping: *damping,
+            },
+        }
+    }
+}
+
+// splits input torque between two outputs, e.g. the wheels of an axle or the front and rear
+// differentials of an awd car. The split is kinematically consistent: input speed =
+// split * speed 0 + (1 - split) * speed 1. The locking torque moves torque from the faster output
+// to the slower one.
+#[derive(Component)]
+pub struct Differential {
+    pub outputs: [DriveOutput; 2],
+    differential_type: DifferentialType,
+    torque_split: f32, // fraction of the input torque to output 0
+    pub input_torque: f32,
+    pub locking_torque: f32,
+    pub output_torques: [f32; 2],
+}
+
+impl Differential {
+    pub fn new(
+        outputs: [DriveOutput; 2],
+        differential_type: DifferentialType,
+        torque_split: f32,
+    ) -> Self {
+        Self {
+            outputs,
+            differential_type,
+            torque_split,
+            input_torque: 0.,
+            locking_torque: 0.,
+            output_torques: [0., 0.],
+        }
+    }
+
+    pub fn from_def(differential_def: &DifferentialDef, outputs: [DriveOutput; 2]) -> Self {
+        Self::new(
+            outputs,
+            DifferentialType::from_def(&differential_def.differential_type),
+            differential_def.torque_split,
+        )
+    }
+
+    pub fn input_speed(
+        &self,
+        differentials: &Query<&mut Differential>,
+        joints: &Query<&mut Joint>,
+    ) -> f32 {
+        let speeds = self
+            .outputs
+            .map(|output| output.speed(differentials, joints));
+        self.torque_split * speeds[0] + (1. - self.torque_split) * speeds[1]
+    }
+
+    // torque from output 0 to output 1, for a speed difference (0 - 1)
+    fn locking_torque(&self, input_torque: f32, input_speed: f32, speed_difference: f32) -> f32 {
+        match self.differential_type {
+            DifferentialType::Open => 0.,
+            DifferentialType::Locked { damping } => damping * speed_difference,
+            DifferentialType::Viscous { coefficient } => coefficient * speed_difference,
+            DifferentialType::ClutchPack {
+                preload,
+                power_ramp_angle,
+                coast_ramp_angle,
+                friction_factor,
+                damping,
+            } => {
+                // driving when the input torque and speed agree, coasting otherwise
+                let ramp_angle = if input_torque * input_speed >= 0. {
+                    power_ramp_angle
+                } else {
+                    coast_ramp_angle
+                };
+                let capacity = preload + friction_factor * input_torque.abs() / ramp_angle.tan();
+                (damping * speed_difference).clamp(-capacity, capacity)
+            }
+        }
+    }
+
+    // split a torque through this differential, and on down the driveline
+    pub fn drive(
+        entity: Entity,
+        torque: f32,
+        differentials: &mut Query<&mut Differential>,
+        joints: &mut Query<&mut Joint>,
+    ) {
+        let Ok(differential) = differentials.get(entity) else {
+            return;
+        };
+        let outputs = differential.outputs;
+        let speeds = outputs.map(|output| output.speed(differentials, joints));
+        let split = differential.torque_split;
+        let input_speed = split * speeds[0] + (1. - split) * speeds[1];
+        let locking_torque =
+            differential.locking_torque(torque, input_speed, speeds[0] - speeds[1]);
+        let output_torques = [
+            split * torque - locking_torque,
+            (1. - split) * torque + locking_torque,
+        ];
+
+        if let Ok(mut differential) = differentials.get_mut(entity) {
+            differential.input_torque = torque;
+            differential.locking_torque = locking_torque;
+            differential.output_torques = output_torques;
+        }
+        for (output, output_torque) in outputs.iter().zip(output_torques) {
+            output.apply_torque(output_torque, differentials, joints);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+    use crate::sva::{Inertia, Xform};
+
+    fn wheel(world: &mut World, speed: f32) -> Entity {
+        let mut joint = Joint::ry("wheel".to_string(), Inertia::zero(), Xform::identity());
+        joint.qd = speed;
+        world.spawn(joint).id()
+    }
+
+    // drives the differential and returns the torque on each wheel
+    fn drive(world: &mut World, differential: Entity, torque: f32, wheels: &[Entity]) -> Vec<f32> {
+        let mut state: SystemState<(Query<&mut Differential>, Query<&mut Joint>)> =
+            SystemState::new(world);
+        let (mut differentials, mut joints) = state.get_mut(world);
+        Differential::drive(differential, torque, &mut differentials, &mut joints);
+        wheels
+            .iter()
+            .map(|wheel| world.get::<Joint>(*wheel).unwrap().tau)
+            .collect()
+    }
+
+    fn assert_torques(torques: &[f32], expected: &[f32]) {
+        assert_eq!(torques.len(), expected.len());
+        for (torque, expected) in torques.iter().zip(expected) {
+            assert!(
+                (torque - expected).abs() < 1.0e-3,
+                "{:?} != {:?}",
+                torques,
+                expected
+            );
+        }
+    }
+
+    // two wheels at 10 and 5 rad/s
+    fn axle(world: &mut World, differential_type: DifferentialType, split: f32) -> [Entity; 3] {
+        let wheels = [wheel(world, 10.), wheel(world, 5.)];
+        let differential = world
+            .spawn(Differential::new(
+                wheels.map(DriveOutput::Wheel),
+                differential_type,
+                split,
+            ))
+            .id();
+        [differential, wheels[0], wheels[1]]
+    }
+
+    fn locking_torque(world: &World, differential: Entity) -> f32 {
+        world
+            .get::<Differential>(differential)
+            .unwrap()
+            .locking_torque
+    }
+
+    #[test]
+    fn open_differential_splits_by_the_torque_split() {
+        let mut world = World::new();
+        let [differential, left, right] = axle(&mut world, DifferentialType::Open, 0.4);
+        let torques = drive(&mut world, differential, 100., &[left, right]);
+        assert_torques(&torques, &[40., 60.]);
+    }
+
+    #[test]
+    fn locked_differential_moves_torque_to_the_slower_wheel() {
+        let mut world = World::new();
+        let locked = DifferentialType::Locked { damping: 1000. };
+        let [differential, left, right] = axle(&mut world, locked, 0.5);
+        let torques = drive(&mut world, differential, 100., &[left, right]);
+        assert_torques(&torques, &[50. - 5000., 50. + 5000.]);
+        assert_torques(&[locking_torque(&world, differential)], &[5000.]);
+    }
+
+    #[test]
+    fn clutch_pack_locking_is_limited_by_the_ramps() {
+        let clutch_pack = DifferentialType::ClutchPack {
+            preload: 20.,
+            power_ramp_angle: PI / 4.,
+            coast_ramp_angle: PI / 3.,
+            friction_factor: 0.5,
+            damping: 1.0e4,
+        };
+
+        // driving: preload + 0.5 * 100 / tan(45 deg)
+        let mut world = World::new();
+        let [differential, left, right] = axle(&mut world, clutch_pack, 0.5);
+        let torques = drive(&mut world, differential, 100., &[left, right]);
+        assert_torques(&torques, &[50. - 70., 50. + 70.]);
+
+        // coasting: preload + 0.5 * 100 / tan(60 deg)
+        let mut world = World::new();
+        let [differential, ..] = axle(&mut world, clutch_pack, 0.5);
+        drive(&mut world, differential, -100., &[]);
+        let expected = 20. + 50. / (PI / 3.).tan();
+        assert_torques(&[locking_torque(&world, differential)], &[expected]);
+    }
+
+    #[test]
+    fn torque_flows_through_nested_differentials() {
+        let mut world = World::new();
+        let [rear, rear_left, rear_right] = axle(&mut world, DifferentialType::Open, 0.5);
+        let front = wheel(&mut world, 10.);
+        let center = world
+            .spawn(Differential::new(
+                [DriveOutput::Wheel(front), DriveOutput::Differential(rear)],
+                DifferentialType::Open,
+                0.4,
+            ))
+            .id();
+
+        let torques = drive(&mut world, center, 100., &[front, rear_left, rear_right]);
+        assert_torques(&torques, &[40., 30., 30.]);
+
+        let mut state: SystemState<Query<&Differential>> = SystemState::new(&mut world);
+        let differentials = state.get(&world);
+        let wheels = DriveOutput::Differential(center).wheels(&differentials);
+        assert_eq!(wheels, [front, rear_left, rear_right]);
+    }
+}
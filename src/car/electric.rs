@@ -0,0 +1,385 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{
+    divergence::RollbackState,
+    joint::Joint,
+    lookup::LookupTable,
+    serialize::{BatteryDef, ElectricDriveDef},
+};
+
+use super::{
+    brakes::Brake,
+    control::CarControl,
+    differential::{Differential, DriveOutput},
+    physics::BrakeWheel,
+};
+
+const RPM_TO_RAD_S: f32 = 2. * PI / 60.;
+
+// battery pack as an open circuit voltage behind an internal resistance
+pub struct Battery {
+    capacity: f32,                     // As
+    open_circuit_voltage: LookupTable, // V against state of charge
+    internal_resistance: f32,
+    min_voltage: f32,
+    max_voltage: f32,
+    pub state_of_charge: f32,
+    pub voltage: f32, // terminal voltage, sags under load
+    pub current: f32, // A, positive when discharging
+}
+
+impl Battery {
+    pub fn new(
+        capacity: f32,
+        open_circuit_voltage: LookupTable,
+        internal_resistance: f32,
+        min_voltage: f32,
+        max_voltage: f32,
+        state_of_charge: f32,
+    ) -> Self {
+        let voltage = open_circuit_voltage.interpolate_1d(state_of_charge);
+        Self {
+            capacity: capacity * 3600.,
+            open_circuit_voltage,
+            internal_resistance,
+            min_voltage,
+            max_voltage,
+            state_of_charge,
+            voltage,
+            current: 0.,
+        }
+    }
+
+    pub fn from_def(battery_def: &BatteryDef) -> Result<Self, String> {
+        Ok(Self::new(
+            battery_def.capacity,
+            LookupTable::curve(&battery_def.open_circuit_voltage)
+                .map_err(|e| format!("open circuit voltage: {}", e))?,
+            battery_def.internal_resistance,
+            battery_def.min_voltage,
+            battery_def.max_voltage,
+            battery_def.initial_state_of_charge,
+        ))
+    }
+
+    fn open_circuit_voltage(&self) -> f32 {
+        self.open_circuit_voltage
+            .interpolate_1d(self.state_of_charge)
+    }
+
+    // terminal power limits [discharge, charge] from the voltage window
+    fn power_limits(&self) -> [f32; 2] {
+        let ocv = self.open_circuit_voltage();
+        let r = self.internal_resistance;
+        if r <= 0. {
+            // ideal cells, only the charge state limits
+            return [
+                if self.state_of_charge > 0. {
+                    f32::INFINITY
+                } else {
+                    0.
+                },
+                if self.state_of_charge < 1. {
+                    f32::INFINITY
+                } else {
+                    0.
+                },
+            ];
+        }
+        let discharge = if self.state_of_charge > 0. {
+            self.min_voltage.max(ocv / 2.) * (ocv - self.min_voltage.max(ocv / 2.)) / r
+        } else {
+            0.
+        };
+        let charge = if self.state_of_charge < 1. {
+            (self.max_voltage * (self.max_voltage - ocv) / r).max(0.)
+        } else {
+            0.
+        };
+        [discharge.max(0.), charge]
+    }
+
+    // power taken from the cells, including the resistive losses
+    fn internal_power(&self) -> f32 {
+        self.open_circuit_voltage() * self.current
+    }
+
+    // draw a terminal power (negative charges), sets the current and sagged voltage
+    fn draw(&mut self, power: f32) {
+        let ocv = self.open_circuit_voltage();
+        let r = self.internal_resistance;
+        if r <= 0. {
+            // ideal cells, no sag
+            self.current = if ocv > 0. { power / ocv } else { 0. };
+            self.voltage = ocv;
+            return;
+        }
+        let discriminant = (ocv * ocv - 4. * r * power).max(0.);
+        self.current = (ocv - discriminant.sqrt()) / (2. * r);
+        self.voltage = ocv - self.current * r;
+    }
+}
+
+// battery electric drive: a motor on a fixed reduction, through a differential or split equally
+// between the driven wheels. The motor is geared rigidly to the wheels, so its inertia belongs in
+// the wheel inertia. Braking is taken by regeneration first, the friction brakes on the driven
+// wheels only make up the rest.
+#[derive(Component)]
+pub struct ElectricDrive {
+    pub wheels: Vec<Entity>,
+    pub differential: Option<Entity>,
+    torque_curve: LookupTable, // maximum motor torque (Nm) against rpm
+    efficiency: LookupTable,   // motor and inverter efficiency against rpm and |torque|
+    gear_ratio: f32,
+    max_power: f32,        // inverter limit, W
+    max_regen_power: f32,  // W
+    regen_fade_speed: f32, // rad/s at the wheels, regen fades out below this
+    pub battery: Battery,
+    pub motor_torque: f32,
+    pub motor_rpm: f32,
+    pub power: f32,              // battery terminal power, W
+    pub energy_used: f32,        // J drawn from the cells
+    pub energy_regenerated: f32, // J put back
+    pub throttle_scale: f32,     // traction control cut
+}
+
+impl ElectricDrive {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        wheels: Vec<Entity>,
+        torque_curve: LookupTable,
+        efficiency: LookupTable,
+        gear_ratio: f32,
+        max_power: f32,
+        max_regen_power: f32,
+        regen_fade_speed: f32,
+        battery: Battery,
+    ) -> Self {
+        Self {
+            wheels,
+            differential: None,
+            torque_curve,
+            efficiency,
+            gear_ratio,
+            max_power,
+            max_regen_power,
+            regen_fade_speed,
+            battery,
+            motor_torque: 0.,
+            motor_rpm: 0.,
+            power: 0.,
+            energy_used: 0.,
+            energy_regenerated: 0.,
+            throttle_scale: 1.,
+        }
+    }
+
+    pub fn with_differential(mut self, differential: Entity) -> Self {
+        self.differential = Some(differential);
+        self
+    }
+
+    pub fn from_def(
+        electric_drive_def: &ElectricDriveDef,
+        wheels: Vec<Entity>,
+    ) -> Result<Self, String> {
+        Ok(Self::new(
+            wheels,
+            LookupTable::curve(&electric_drive_def.torque_curve)
+                .map_err(|e| format!("torque curve: {}", e))?,
+            LookupTable::new(
+                vec![
+                    electric_drive_def.efficiency_rpm.clone(),
+                    electric_drive_def.efficiency_torque.clone(),
+                ],
+                electric_drive_def.efficiency.clone(),
+            )
+            .map_err(|e| format!("efficiency map: {}", e))?,
+            electric_drive_def.gear_ratio,
+            electric_drive_def.max_power,
+            electric_drive_def.max_regen_power,
+            electric_drive_def.regen_fade_speed,
+            Battery::from_def(&electric_drive_def.battery)
+                .map_err(|e| format!("battery: {}", e))?,
+        ))
+    }
+
+    // net energy taken from the battery, J
+    pub fn energy(&self) -> f32 {
+        self.energy_used - self.energy_regenerated
+    }
+
+    // battery terminal power for a motor torque at a motor speed
+    fn electrical_power(&self, torque: f32, speed: f32) -> f32 {
+        let efficiency = self
+            .efficiency
+            .interpolate(&[speed.abs() / RPM_TO_RAD_S, torque.abs()])
+            .max(0.01);
+        let mechanical = torque * speed;
+        if mechanical > 0. {
+            mechanical / efficiency
+        } else {
+            mechanical * efficiency
+        }
+    }
+
+    // scale a torque down until its electrical power fits between the limits
+    fn limit_torque(&self, torque: f32, speed: f32) -> f32 {
+        let battery_limits = self.battery.power_limits();
+        let power = self.electrical_power(torque, speed);
+        let limit = if power > 0. {
+            self.max_power.min(battery_limits[0])
+        } else {
+            self.max_regen_power.min(battery_limits[1])
+        };
+        if power.abs() > limit {
+            torque * limit / power.abs()
+        } else {
+            torque
+        }
+    }
+}
+
+impl RollbackState for ElectricDrive {
+    type State = [f32; 6];
+    fn save(&self) -> Self::State {
+        [
+            self.battery.state_of_charge,
+            self.battery.voltage,
+            self.battery.current,
+            self.energy_used,
+            self.energy_regenerated,
+            self.throttle_scale,
+        ]
+    }
+    fn restore(&mut self, state: &Self::State) {
+        [
+            self.battery.state_of_charge,
+            self.battery.voltage,
+            self.battery.current,
+            self.energy_used,
+            self.energy_regenerated,
+            self.throttle_scale,
+        ] = *state;
+    }
+}
+
+// motor torque from the throttle, regenerative braking from the brake pedal
+pub fn electric_drive_system(
+    mut drives: Query<&mut ElectricDrive>,
+    mut differentials: Query<&mut Differential>,
+    mut joints: Query<&mut Joint>,
+    mut brake_wheels: Query<&mut BrakeWheel>,
+    mut brakes: Query<&mut Brake>,
+    control: Res<CarControl>,
+) {
+    for mut drive in drives.iter_mut() {
+        let wheel_speed = match drive.differential {
+            Some(differential) => {
+                DriveOutput::Differential(differential).speed(&differentials, &joints)
+            }
+            None => {
+                let wheel_speeds: Vec<f32> = drive
+                    .wheels
+                    .iter()
+                    .filter_map(|wheel| joints.get(*wheel).ok().map(|joint| joint.qd))
+                    .collect();
+                wheel_speeds.iter().sum::<f32>() / wheel_speeds.len().max(1) as f32
+            }
+        };
+        let motor_speed = wheel_speed * drive.gear_ratio;
+        let max_torque = drive
+            .torque_curve
+            .interpolate_1d(motor_speed.abs() / RPM_TO_RAD_S);
+
+        // gear: -1 reverse, 0 neutral, forward otherwise
+        let direction = control.gear.signum() as f32;
+        let drive_torque = direction * drive.throttle_scale * control.throttle * max_torque;
+
+        // regen takes the brake torque asked of the driven wheels, against their rotation. With
+        // a differential those are the wheels at its outputs.
+        let regen_wheels = match drive.differential {
+            Some(differential) => {
+                DriveOutput::Differential(differential).wheels(&differentials.to_readonly())
+            }
+            None => drive.wheels.clone(),
+        };
+        let requested: Vec<f32> = regen_wheels
+            .iter()
+            .map(|wheel| {
+                brakes.get(*wheel).map_or_else(
+                    |_| {
+                        brake_wheels.get(*wheel).map_or(0., |brake| {
+                            brake.modulation * control.brake * brake.max_torque
+                        })
+                    },
+                    |brake| brake.modulation * brake.pressure_torque,
+                )
+            })
+            .collect();
+        let requested_total: f32 = requested.iter().sum();
+        let fade = (wheel_speed.abs() / drive.regen_fade_speed).min(1.);
+        let regen_torque = if drive_torque == 0. {
+            -wheel_speed.signum() * (fade * requested_total / drive.gear_ratio).min(max_torque)
+        } else {
+            0.
+        };
+
+        let motor_torque = drive.limit_torque(drive_torque + regen_torque, motor_speed);
+
+        // share the achieved regen between the wheels in proportion to what they asked for
+        let achieved_regen = if drive_torque == 0. {
+            (-motor_torque * wheel_speed.signum()).max(0.) * drive.gear_ratio
+        } else {
+            0.
+        };
+        for (wheel, requested) in regen_wheels.iter().zip(requested.iter()) {
+            let regen_torque = if requested_total > 0. {
+                achieved_regen * requested / requested_total
+            } else {
+                0.
+            };
+            if let Ok(mut brake) = brakes.get_mut(*wheel) {
+                brake.regen_torque = regen_torque;
+            } else if let Ok(mut brake) = brake_wheels.get_mut(*wheel) {
+                brake.regen_torque = regen_torque;
+            }
+        }
+
+        let power = drive.electrical_power(motor_torque, motor_speed);
+        drive.battery.draw(power);
+        drive.power = power;
+        drive.motor_torque = motor_torque;
+        drive.motor_rpm = motor_speed / RPM_TO_RAD_S;
+
+        let wheel_torque = motor_torque * drive.gear_ratio;
+        if let Some(differential) = drive.differential {
+            Differential::drive(differential, wheel_torque, &mut differentials, &mut joints);
+        } else {
+            let torque = wheel_torque / drive.wheels.len().max(1) as f32;
+            for wheel in drive.wheels.iter() {
+                if let Ok(mut joint) = joints.get_mut(*wheel) {
+                    joint.tau += torque;
+                }
+            }
+        }
+    }
+}
+
+// state of charge and energy, once per step
+pub fn battery_system(mut drives: Query<&mut ElectricDrive>, fixed_time: Res<FixedTime>) {
+    let dt = fixed_time.period.as_secs_f32();
+    for mut drive in drives.iter_mut() {
+        let power = drive.battery.internal_power();
+        let battery = &mut drive.battery;
+        battery.state_of_charge =
+            (battery.state_of_charge - battery.current * dt / battery.capacity).clamp(0., 1.);
+        if power > 0. {
+            drive.energy_used += power * dt;
+        } else {
+            drive.energy_regenerated -= power * dt;
+        }
+    }
+}
@@ -2,10 +2,13 @@ use std::f32::consts::PI;
 
 use bevy::prelude::*;
 
+use crate::terrain::Terrain;
+
 pub fn build_environment(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
+    terrain: &Terrain,
 ) {
     // add ambient light
     commands.insert_resource(AmbientLight {
@@ -27,23 +30,44 @@ pub fn build_environment(
         ..default()
     });
 
-    // add ground plane
-    let ground_transform = Transform::from_translation(Vec3 {
-        x: 0.,
-        y: 0.,
-        z: 0.,
-    }) * Transform::from_rotation(Quat::from_axis_angle(Vec3::X, PI / 2.));
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Plane {
-            size: 1000.0,
-            subdivisions: 10,
-        })),
-        material: materials.add(StandardMaterial {
-            base_color: Color::WHITE,
-            perceptual_roughness: 1.0,
-            ..default()
-        }),
-        transform: ground_transform,
+    // add ground
+    let ground_material = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        perceptual_roughness: 1.0,
         ..default()
     });
+    match terrain {
+        Terrain::Flat => {
+            let ground_transform =
+                Transform::from_translation(Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                }) * Transform::from_rotation(Quat::from_axis_angle(Vec3::X, PI / 2.));
+            commands.spawn(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Plane {
+                    size: 1000.0,
+                    subdivisions: 10,
+                })),
+                material: ground_material,
+                transform: ground_transform,
+                ..default()
+            });
+        }
+        Terrain::Heightfield(heightfield) => {
+            // heightfield vertices are already in the z up coordinate system
+            commands.spawn(PbrBundle {
+                mesh: meshes.add(heightfield.to_bevy_mesh()),
+                material: ground_material,
+                ..default()
+            });
+        }
+        Terrain::Mesh(mesh_terrain) => {
+            commands.spawn(PbrBundle {
+                mesh: meshes.add(mesh_terrain.mesh.to_bevy_mesh()),
+                material: ground_material,
+                ..default()
+            });
+        }
+    }
 }
@@ -1,9 +1,16 @@
+pub mod aero;
+mod assist;
+mod brakes;
 mod build;
 mod build_from_json;
 mod camera_az_el;
 mod control;
 mod create_car_json;
+mod differential;
+pub mod electric;
 mod environment;
+mod pacejka;
 mod physics;
 pub mod plugin;
+mod powertrain;
 mod schedule;
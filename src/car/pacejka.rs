@@ -0,0 +1,447 @@
+use std::{
+    collections::HashMap,
+    f32::consts::{FRAC_PI_2, PI},
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+use bevy::prelude::*;
+
+use crate::{joint::Joint, serialize::PacejkaTireDef, surface::SurfaceMap, terrain::Terrain};
+
+use super::physics::{ContactPatch, TireCondition, TireState};
+
+// declares the parameter struct and reads each field from the .tir keys, with its default
+macro_rules! tir_parameters {
+    ($($field:ident: $key:literal = $default:expr,)*) => {
+        // magic formula '02 parameters, named as in the .tir file
+        #[derive(Debug, Clone)]
+        pub struct PacejkaParameters {
+            $(pub $field: f32,)*
+        }
+
+        impl PacejkaParameters {
+            pub fn from_values(values: &HashMap<String, f32>) -> Self {
+                Self {
+                    $($field: values.get($key).copied().unwrap_or($default),)*
+                }
+            }
+        }
+
+        impl Default for PacejkaParameters {
+            fn default() -> Self {
+                Self {
+                    $($field: $default,)*
+                }
+            }
+        }
+    };
+}
+
+tir_parameters! {
+    // dimensions, operating conditions and vertical
+    unloaded_radius: "UNLOADED_RADIUS" = 0.3,
+    fnomin: "FNOMIN" = 4000.,
+    vertical_stiffness: "VERTICAL_STIFFNESS" = 200000.,
+    vertical_damping: "VERTICAL_DAMPING" = 500.,
+    // slip limits
+    kpumin: "KPUMIN" = -1.5,
+    kpumax: "KPUMAX" = 1.5,
+    alpmin: "ALPMIN" = -FRAC_PI_2,
+    alpmax: "ALPMAX" = FRAC_PI_2,
+    // scaling factors
+    lfzo: "LFZO" = 1.,
+    lcx: "LCX" = 1.,
+    lmux: "LMUX" = 1.,
+    lex: "LEX" = 1.,
+    lkx: "LKX" = 1.,
+    lhx: "LHX" = 1.,
+    lvx: "LVX" = 1.,
+    lcy: "LCY" = 1.,
+    lmuy: "LMUY" = 1.,
+    ley: "LEY" = 1.,
+    lky: "LKY" = 1.,
+    lhy: "LHY" = 1.,
+    lvy: "LVY" = 1.,
+    ltr: "LTR" = 1.,
+    lres: "LRES" = 1.,
+    lxal: "LXAL" = 1.,
+    lyka: "LYKA" = 1.,
+    lvyka: "LVYKA" = 1.,
+    ls: "LS" = 1.,
+    // longitudinal
+    pcx1: "PCX1" = 1.65,
+    pdx1: "PDX1" = 1.,
+    pdx2: "PDX2" = 0.,
+    pdx3: "PDX3" = 0.,
+    pex1: "PEX1" = 0.,
+    pex2: "PEX2" = 0.,
+    pex3: "PEX3" = 0.,
+    pex4: "PEX4" = 0.,
+    pkx1: "PKX1" = 20.,
+    pkx2: "PKX2" = 0.,
+    pkx3: "PKX3" = 0.,
+    phx1: "PHX1" = 0.,
+    phx2: "PHX2" = 0.,
+    pvx1: "PVX1" = 0.,
+    pvx2: "PVX2" = 0.,
+    rbx1: "RBX1" = 10.,
+    rbx2: "RBX2" = 6.,
+    rcx1: "RCX1" = 1.,
+    rex1: "REX1" = 0.,
+    rex2: "REX2" = 0.,
+    rhx1: "RHX1" = 0.,
+    // lateral
+    pcy1: "PCY1" = 1.3,
+    pdy1: "PDY1" = 1.,
+    pdy2: "PDY2" = 0.,
+    pdy3: "PDY3" = 0.,
+    pey1: "PEY1" = -1.,
+    pey2: "PEY2" = 0.,
+    pey3: "PEY3" = 0.,
+    pey4: "PEY4" = 0.,
+    pky1: "PKY1" = -20.,
+    pky2: "PKY2" = 1.,
+    pky3: "PKY3" = 0.,
+    phy1: "PHY1" = 0.,
+    phy2: "PHY2" = 0.,
+    phy3: "PHY3" = 0.,
+    pvy1: "PVY1" = 0.,
+    pvy2: "PVY2" = 0.,
+    pvy3: "PVY3" = 0.,
+    pvy4: "PVY4" = 0.,
+    rby1: "RBY1" = 10.,
+    rby2: "RBY2" = 10.,
+    rby3: "RBY3" = 0.,
+    rcy1: "RCY1" = 1.,
+    rey1: "REY1" = 0.,
+    rey2: "REY2" = 0.,
+    rhy1: "RHY1" = 0.,
+    rhy2: "RHY2" = 0.,
+    rvy1: "RVY1" = 0.,
+    rvy2: "RVY2" = 0.,
+    rvy3: "RVY3" = 0.,
+    rvy4: "RVY4" = 0.,
+    rvy5: "RVY5" = 0.,
+    rvy6: "RVY6" = 0.,
+    // aligning
+    qbz1: "QBZ1" = 10.,
+    qbz2: "QBZ2" = 0.,
+    qbz3: "QBZ3" = 0.,
+    qbz4: "QBZ4" = 0.,
+    qbz5: "QBZ5" = 0.,
+    qbz9: "QBZ9" = 0.,
+    qbz10: "QBZ10" = 0.,
+    qcz1: "QCZ1" = 1.1,
+    qdz1: "QDZ1" = 0.1,
+    qdz2: "QDZ2" = 0.,
+    qdz3: "QDZ3" = 0.,
+    qdz4: "QDZ4" = 0.,
+    qdz6: "QDZ6" = 0.,
+    qdz7: "QDZ7" = 0.,
+    qdz8: "QDZ8" = 0.,
+    qdz9: "QDZ9" = 0.,
+    qez1: "QEZ1" = 0.,
+    qez2: "QEZ2" = 0.,
+    qez3: "QEZ3" = 0.,
+    qez4: "QEZ4" = 0.,
+    qez5: "QEZ5" = 0.,
+    qhz1: "QHZ1" = 0.,
+    qhz2: "QHZ2" = 0.,
+    qhz3: "QHZ3" = 0.,
+    qhz4: "QHZ4" = 0.,
+    ssz1: "SSZ1" = 0.,
+    ssz2: "SSZ2" = 0.,
+    ssz3: "SSZ3" = 0.,
+    ssz4: "SSZ4" = 0.,
+}
+
+// the numeric values of a .tir file, by upper case key. Sections, strings and comments
+// ($ or !) are ignored.
+pub fn read_tir_values(text: &str) -> HashMap<String, f32> {
+    let mut values = HashMap::new();
+    for line in text.lines() {
+        let line = line.split(['$', '!']).next().unwrap_or("");
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if let Ok(value) = value.trim().parse::<f32>() {
+            values.insert(key.trim().to_uppercase(), value);
+        }
+    }
+    values
+}
+
+impl PacejkaParameters {
+    pub fn from_tir(text: &str) -> Self {
+        Self::from_values(&read_tir_values(text))
+    }
+
+    pub fn from_tir_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Ok(Self::from_tir(&text))
+    }
+
+    // fx, fy, mz (iso axes: x forward, y left, z up) for a vertical load, slip ratio, slip
+    // angle (rad) and camber (rad). mu_scale multiplies both friction coefficients.
+    pub fn forces(
+        &self,
+        fz: f32,
+        kappa: f32,
+        alpha: f32,
+        gamma: f32,
+        mu_scale: f32,
+    ) -> (f32, f32, f32) {
+        let p = self;
+        let fz0 = p.fnomin * p.lfzo;
+        let dfz = (fz - fz0) / fz0;
+        let kappa = kappa.clamp(p.kpumin, p.kpumax);
+        let alpha = alpha.clamp(p.alpmin, p.alpmax);
+        let r0 = p.unloaded_radius;
+        let eps = 1.0e-6;
+
+        // pure longitudinal slip
+        let shx = (p.phx1 + p.phx2 * dfz) * p.lhx;
+        let kx = kappa + shx;
+        let cx = p.pcx1 * p.lcx;
+        let mux = (p.pdx1 + p.pdx2 * dfz) * (1. - p.pdx3 * gamma * gamma) * p.lmux * mu_scale;
+        let dx = mux * fz;
+        let ex =
+            ((p.pex1 + p.pex2 * dfz + p.pex3 * dfz * dfz) * (1. - p.pex4 * kx.signum()) * p.lex)
+                .min(1.);
+        let kxk = fz * (p.pkx1 + p.pkx2 * dfz) * (p.pkx3 * dfz).exp() * p.lkx;
+        let bx = kxk / (cx * dx + eps);
+        let svx = fz * (p.pvx1 + p.pvx2 * dfz) * p.lvx * p.lmux * mu_scale;
+        let fx0 = magic_formula(bx, cx, dx, ex, kx) + svx;
+
+        // pure lateral slip
+        let shy = (p.phy1 + p.phy2 * dfz) * p.lhy + p.phy3 * gamma;
+        let ay = alpha + shy;
+        let cy = p.pcy1 * p.lcy;
+        let muy = (p.pdy1 + p.pdy2 * dfz) * (1. - p.pdy3 * gamma * gamma) * p.lmuy * mu_scale;
+        let dy = muy * fz;
+        let ey = ((p.pey1 + p.pey2 * dfz) * (1. - (p.pey3 + p.pey4 * gamma) * ay.signum()) * p.ley)
+            .min(1.);
+        let kya = p.pky1
+            * fz0
+            * (2. * (fz / (p.pky2 * fz0)).atan()).sin()
+            * (1. - p.pky3 * gamma.abs())
+            * p.lky;
+        let by = kya / (cy * dy + eps);
+        let svy = fz
+            * ((p.pvy1 + p.pvy2 * dfz) * p.lvy + (p.pvy3 + p.pvy4 * dfz) * gamma)
+            * p.lmuy
+            * mu_scale;
+        let fy0 = magic_formula(by, cy, dy, ey, ay) + svy;
+
+        // combined slip, longitudinal
+        let shxa = p.rhx1;
+        let bxa = p.rbx1 * (p.rbx2 * kappa).atan().cos() * p.lxal;
+        let cxa = p.rcx1;
+        let exa = (p.rex1 + p.rex2 * dfz).min(1.);
+        let gxa = weighting(bxa, cxa, exa, alpha + shxa) / weighting(bxa, cxa, exa, shxa);
+        let fx = gxa * fx0;
+
+        // combined slip, lateral
+        let shyk = p.rhy1 + p.rhy2 * dfz;
+        let byk = p.rby1 * (p.rby2 * (alpha - p.rby3)).atan().cos() * p.lyka;
+        let cyk = p.rcy1;
+        let eyk = (p.rey1 + p.rey2 * dfz).min(1.);
+        let dvyk =
+            muy * fz * (p.rvy1 + p.rvy2 * dfz + p.rvy3 * gamma) * (p.rvy4 * alpha).atan().cos();
+        let svyk = dvyk * (p.rvy5 * (p.rvy6 * kappa).atan()).sin() * p.lvyka;
+        let gyk = weighting(byk, cyk, eyk, kappa + shyk) / weighting(byk, cyk, eyk, shyk);
+        let fy = gyk * fy0 + svyk;
+
+        // aligning moment, pneumatic trail and residual moment with equivalent slip angles
+        let sht = p.qhz1 + p.qhz2 * dfz + (p.qhz3 + p.qhz4 * dfz) * gamma;
+        let at = alpha + sht;
+        let bt = (p.qbz1 + p.qbz2 * dfz + p.qbz3 * dfz * dfz)
+            * (1. + p.qbz4 * gamma + p.qbz5 * gamma.abs())
+            * p.lky
+            / p.lmuy;
+        let ct = p.qcz1;
+        let dt = fz
+            * (p.qdz1 + p.qdz2 * dfz)
+            * (1. + p.qdz3 * gamma + p.qdz4 * gamma * gamma)
+            * (r0 / fz0)
+            * p.ltr;
+        let et = ((p.qez1 + p.qez2 * dfz + p.qez3 * dfz * dfz)
+            * (1. + (p.qez4 + p.qez5 * gamma) * (2. / PI) * (bt * ct * at).atan()))
+        .min(1.);
+        let shf = shy + svy / (kya + eps);
+        let ar = alpha + shf;
+        let br = p.qbz9 * p.lky / p.lmuy + p.qbz10 * by * cy;
+        let dr = fz
+            * ((p.qdz6 + p.qdz7 * dfz) * p.lres + (p.qdz8 + p.qdz9 * dfz) * gamma)
+            * r0
+            * p.lmuy
+            * mu_scale;
+
+        let slip_ratio = kxk / (kya + eps);
+        let at_eq = (at * at + (slip_ratio * kappa).powi(2)).sqrt() * at.signum();
+        let ar_eq = (ar * ar + (slip_ratio * kappa).powi(2)).sqrt() * ar.signum();
+        let trail = dt
+            * (ct * (bt * at_eq - et * (bt * at_eq - (bt * at_eq).atan())).atan()).cos()
+            * alpha.cos();
+        let mzr = dr * (br * ar_eq).atan().cos() * alpha.cos();
+        let s = (p.ssz1 + p.ssz2 * (fy / fz0) + (p.ssz3 + p.ssz4 * dfz) * gamma) * r0 * p.ls;
+        let mz = -trail * (fy - svyk) + mzr + s * fx;
+
+        (fx, fy, mz)
+    }
+}
+
+fn magic_formula(b: f32, c: f32, d: f32, e: f32, x: f32) -> f32 {
+    d * (c * (b * x - e * (b * x - (b * x).atan())).atan()).sin()
+}
+
+// combined slip weighting function, without the peak factor
+fn weighting(b: f32, c: f32, e: f32, x: f32) -> f32 {
+    (c * (b * x - e * (b * x - (b * x).atan())).atan()).cos()
+}
+
+// tire with forces from the pacejka '02 magic formula. Use instead of TireContact.
+#[derive(Component)]
+pub struct PacejkaTire {
+    pub parameters: PacejkaParameters,
+}
+
+impl PacejkaTire {
+    pub fn new(parameters: PacejkaParameters) -> Self {
+        Self { parameters }
+    }
+
+    pub fn from_def(pacejka_tire_def: &PacejkaTireDef) -> Result<Self, String> {
+        Ok(Self::new(PacejkaParameters::from_tir_file(
+            &pacejka_tire_def.tir_file,
+        )?))
+    }
+}
+
+pub fn pacejka_tire_system(
+    mut joints: Query<(
+        &mut Joint,
+        &PacejkaTire,
+        Option<&mut TireCondition>,
+        Option<&mut TireState>,
+    )>,
+    terrain: Res<Terrain>,
+    surfaces: Res<SurfaceMap>,
+) {
+    for (mut joint, tire, mut condition, mut state) in joints.iter_mut() {
+        if let Some(condition) = condition.as_mut() {
+            condition.clear_contact();
+        }
+        if let Some(state) = state.as_mut() {
+            state.clear();
+        }
+        let p = &tire.parameters;
+        let patch = ContactPatch::new(&joint, p.unloaded_radius, &terrain);
+        if patch.deflection <= 0. {
+            continue;
+        }
+
+        // vertical
+        let spring_force = p.vertical_stiffness * patch.deflection;
+        let damping_force = -p.vertical_damping * patch.tire_up.dot(&patch.velocity);
+        let fz = (spring_force + damping_force).max(0.);
+
+        let slip = patch.slip(joint.qd);
+        let material = surfaces.material_at(&patch.surface);
+        let grip = condition.as_ref().map_or(1., |condition| condition.grip());
+        let (fx, fy, mz) = p.forces(
+            fz,
+            slip.kappa,
+            slip.alpha,
+            slip.gamma,
+            grip * material.friction_scale,
+        );
+        if let Some(condition) = condition.as_mut() {
+            let slip_power = patch.slip_power(fx, fy);
+            let rolling_resistance_scale = material.rolling_resistance_scale;
+            condition.contact(
+                &mut joint,
+                fz,
+                patch.height,
+                slip_power,
+                rolling_resistance_scale,
+            );
+        }
+
+        joint.f_ext += patch.force(fx, fy, fz, mz);
+        if let Some(state) = state.as_mut() {
+            state.add(&patch, fz, fx, fy, mz);
+            state.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(value: f32, reference: f32, tolerance: f32) -> bool {
+        (value - reference).abs() <= tolerance * reference.abs()
+    }
+
+    #[test]
+    fn slip_stiffness_at_nominal_load() {
+        // kx = fz pkx1 and ky = pky1 fz0 sin(2 atan(1)) at the nominal load
+        let p = PacejkaParameters::default();
+        let slip = 1.0e-4;
+        let (fx, _, _) = p.forces(p.fnomin, slip, 0., 0., 1.);
+        let (_, fy, _) = p.forces(p.fnomin, 0., slip, 0., 1.);
+        assert!(close(fx / slip, p.fnomin * p.pkx1, 0.01));
+        assert!(close(fy / slip, p.pky1 * p.fnomin, 0.01));
+    }
+
+    #[test]
+    fn pure_slip_reference_values() {
+        let p = PacejkaParameters::default();
+        // d sin(c atan(b x - e (b x - atan(b x)))) with the default coefficients
+        let (fx, fy, _) = p.forces(4000., 0.1, 0., 0., 1.);
+        assert!(close(fx, 3972.58, 1.0e-4));
+        assert_eq!(fy, 0.);
+        let (fx, fy, _) = p.forces(4000., 0., 0.05, 0., 1.);
+        assert_eq!(fx, 0.);
+        assert!(close(fy, -3230.65, 1.0e-4));
+    }
+
+    #[test]
+    fn peak_force_is_the_friction_limit() {
+        // the sine peaks where c atan(b x) = pi / 2
+        let p = PacejkaParameters::default();
+        let bx = p.fnomin * p.pkx1 / (p.pcx1 * p.fnomin);
+        let kappa = (FRAC_PI_2 / p.pcx1).tan() / bx;
+        let (fx, _, _) = p.forces(p.fnomin, kappa, 0., 0., 1.);
+        assert!(close(fx, p.pdx1 * p.fnomin, 1.0e-4));
+        let (fx, _, _) = p.forces(p.fnomin, kappa, 0., 0., 0.5);
+        assert!(fx < 0.51 * p.pdx1 * p.fnomin);
+    }
+
+    #[test]
+    fn combined_slip_reduces_both_forces() {
+        let p = PacejkaParameters::default();
+        let (fx_pure, _, _) = p.forces(4000., 0.05, 0., 0., 1.);
+        let (_, fy_pure, _) = p.forces(4000., 0., 0.05, 0., 1.);
+        let (fx, fy, _) = p.forces(4000., 0.05, 0.05, 0., 1.);
+        assert!(fx.abs() < fx_pure.abs());
+        assert!(fy.abs() < fy_pure.abs());
+    }
+
+    #[test]
+    fn tir_values_override_the_defaults() {
+        let text =
+            "[VERTICAL]\nFNOMIN = 5000 $ nominal load\nPCX1 = 1.5 ! shape\nFILE_TYPE = 'tir'\n";
+        let p = PacejkaParameters::from_tir(text);
+        assert_eq!(p.fnomin, 5000.);
+        assert_eq!(p.pcx1, 1.5);
+        assert_eq!(p.pdx1, PacejkaParameters::default().pdx1);
+    }
+}
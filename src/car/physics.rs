@@ -1,32 +1,377 @@
+use std::{fs::File, io::Read, path::Path};
+
 use bevy::prelude::*;
 
 use crate::{
+    divergence::RollbackState,
     joint::Joint,
-    serialize::{BrakeWheelDef, DrivenWheelDef, SteeringDef, SuspensionDef, TireContactDef},
-    sva::{Force, Vector},
+    lookup::LookupTable,
+    serialize::{
+        AntiRollBarDef, BrakeWheelDef, DrivenWheelDef, KinematicCurveDef, SteeringDef,
+        SteeringRackDef, SuspensionDef, SuspensionKinematicsDef, TableTireDef, TireConditionDef,
+        TireContactDef, TireRelaxationDef, TravelStopDef,
+    },
+    surface::SurfaceMap,
+    sva::{Force, Vector, Xform},
+    terrain::{SurfacePoint, Terrain},
 };
 
 use super::control::CarControl;
 
+// progressive stop at the end of travel, force = stiffness * penetration^exponent
+#[derive(Debug, Clone, Copy)]
+pub struct TravelStop {
+    position: f32, // joint travel where the stop starts to touch
+    stiffness: f32,
+    exponent: f32,
+}
+
+impl TravelStop {
+    pub fn new(position: f32, stiffness: f32, exponent: f32) -> Self {
+        Self {
+            position,
+            stiffness,
+            exponent,
+        }
+    }
+
+    pub fn from_def(travel_stop_def: &TravelStopDef) -> Self {
+        Self::new(
+            travel_stop_def.position,
+            travel_stop_def.stiffness,
+            travel_stop_def.exponent,
+        )
+    }
+
+    fn force(&self, penetration: f32) -> f32 {
+        self.stiffness * penetration.max(0.).powf(self.exponent)
+    }
+}
+
+// spring and damper on a suspension joint, positive travel is bump. Without curves the spring and
+// damper are linear. Curves are clamped past their ends, so they must cover the full travel.
 #[derive(Component)]
 pub struct Suspension {
     stiffness: f32,
     damping: f32,
+    spring_curve: Option<LookupTable>, // force against compression from ride height
+    preload: f32,                      // spring force at ride height
+    ride_height: f32,                  // joint travel where the spring compression is zero
+    bump_curve: Option<LookupTable>,   // damper force against bump speed
+    rebound_curve: Option<LookupTable>, // damper force against rebound speed, both positive
+    bump_stop: Option<TravelStop>,
+    rebound_stop: Option<TravelStop>,
 }
 
 impl Suspension {
     pub fn new(stiffness: f32, damping: f32) -> Self {
-        Self { stiffness, damping }
+        Self {
+            stiffness,
+            damping,
+            spring_curve: None,
+            preload: 0.,
+            ride_height: 0.,
+            bump_curve: None,
+            rebound_curve: None,
+            bump_stop: None,
+            rebound_stop: None,
+        }
+    }
+
+    pub fn with_spring_curve(
+        mut self,
+        spring_curve: Option<LookupTable>,
+        preload: f32,
+        ride_height: f32,
+    ) -> Self {
+        self.spring_curve = spring_curve;
+        self.preload = preload;
+        self.ride_height = ride_height;
+        self
+    }
+
+    pub fn with_damper_curves(
+        mut self,
+        bump_curve: Option<LookupTable>,
+        rebound_curve: Option<LookupTable>,
+    ) -> Self {
+        self.bump_curve = bump_curve;
+        self.rebound_curve = rebound_curve;
+        self
+    }
+
+    pub fn with_stops(
+        mut self,
+        bump_stop: Option<TravelStop>,
+        rebound_stop: Option<TravelStop>,
+    ) -> Self {
+        self.bump_stop = bump_stop;
+        self.rebound_stop = rebound_stop;
+        self
     }
 
-    pub fn from_def(suspension_def: &SuspensionDef) -> Self {
-        Self::new(suspension_def.stiffness, suspension_def.damping)
+    pub fn from_def(suspension_def: &SuspensionDef) -> Result<Self, String> {
+        let curve = |points: &Option<Vec<[f32; 2]>>, name: &str| {
+            points
+                .as_deref()
+                .map(LookupTable::curve)
+                .transpose()
+                .map_err(|e| format!("{}: {}", name, e))
+        };
+        Ok(Self::new(suspension_def.stiffness, suspension_def.damping)
+            .with_spring_curve(
+                curve(&suspension_def.spring_curve, "spring curve")?,
+                suspension_def.preload,
+                suspension_def.ride_height,
+            )
+            .with_damper_curves(
+                curve(&suspension_def.bump_curve, "bump curve")?,
+                curve(&suspension_def.rebound_curve, "rebound curve")?,
+            )
+            .with_stops(
+                suspension_def.bump_stop.as_ref().map(TravelStop::from_def),
+                suspension_def
+                    .rebound_stop
+                    .as_ref()
+                    .map(TravelStop::from_def),
+            ))
+    }
+
+    // joint travel where the spring compression is zero, the reference for the kinematic curves
+    pub fn ride_height(&self) -> f32 {
+        self.ride_height
+    }
+
+    // force pushing the joint towards rebound
+    fn spring_force(&self, q: f32) -> f32 {
+        let compression = q - self.ride_height;
+        let spring = match &self.spring_curve {
+            Some(curve) => curve.interpolate_1d(compression),
+            None => self.stiffness * compression,
+        };
+        let bump_stop = self
+            .bump_stop
+            .map_or(0., |stop| stop.force(q - stop.position));
+        let rebound_stop = self
+            .rebound_stop
+            .map_or(0., |stop| stop.force(stop.position - q));
+        self.preload + spring + bump_stop - rebound_stop
+    }
+
+    fn damper_force(&self, qd: f32) -> f32 {
+        let curve = if qd > 0. {
+            &self.bump_curve
+        } else {
+            &self.rebound_curve
+        };
+        match curve {
+            Some(curve) => qd.signum() * curve.interpolate_1d(qd.abs()),
+            None => self.damping * qd,
+        }
     }
 }
 
 pub fn suspension_system(mut joints: Query<(&mut Joint, &Suspension)>) {
     for (mut joint, suspension) in joints.iter_mut() {
-        joint.tau -= suspension.stiffness * joint.q + suspension.damping * joint.qd;
+        joint.tau -= suspension.spring_force(joint.q) + suspension.damper_force(joint.qd);
+    }
+}
+
+// couples the left and right suspension joints of an axle: equal and opposite forces in
+// proportion to their travel difference, so only roll is resisted
+#[derive(Component)]
+pub struct AntiRollBar {
+    pub left: Entity,
+    pub right: Entity,
+    stiffness: f32, // N/m of travel difference, at the wheels
+    pub force: f32,
+}
+
+impl AntiRollBar {
+    pub fn new(left: Entity, right: Entity, stiffness: f32) -> Self {
+        Self {
+            left,
+            right,
+            stiffness,
+            force: 0.,
+        }
+    }
+
+    pub fn from_def(anti_roll_bar_def: &AntiRollBarDef, left: Entity, right: Entity) -> Self {
+        Self::new(left, right, anti_roll_bar_def.stiffness)
+    }
+}
+
+pub fn anti_roll_bar_system(mut bars: Query<&mut AntiRollBar>, mut joints: Query<&mut Joint>) {
+    for mut bar in bars.iter_mut() {
+        let (Ok(left), Ok(right)) = (joints.get(bar.left), joints.get(bar.right)) else {
+            continue;
+        };
+        bar.force = bar.stiffness * (left.q - right.q);
+
+        if let Ok(mut left) = joints.get_mut(bar.left) {
+            left.tau -= bar.force;
+        }
+        if let Ok(mut right) = joints.get_mut(bar.right) {
+            right.tau += bar.force;
+        }
+    }
+}
+
+// alignment change against suspension travel (m) and steer angle (rad)
+pub enum KinematicCurve {
+    Table(LookupTable), // axes [travel, steer]
+    Polynomial {
+        travel: Vec<f32>, // coefficients from the constant term up
+        steer: Vec<f32>,  // coefficients from the linear term up
+    },
+}
+
+impl KinematicCurve {
+    pub fn from_def(kinematic_curve_def: &KinematicCurveDef) -> Result<Self, String> {
+        Ok(match kinematic_curve_def {
+            KinematicCurveDef::Table {
+                travel,
+                steer,
+                values,
+            } => KinematicCurve::Table(LookupTable::new(
+                vec![travel.clone(), steer.clone()],
+                values.clone(),
+            )?),
+            KinematicCurveDef::Polynomial { travel, steer } => KinematicCurve::Polynomial {
+                travel: travel.clone(),
+                steer: steer.clone(),
+            },
+        })
+    }
+
+    fn value(&self, travel: f32, steer: f32) -> f32 {
+        match self {
+            KinematicCurve::Table(table) => table.interpolate(&[travel, steer]),
+            KinematicCurve::Polynomial {
+                travel: travel_coefficients,
+                steer: steer_coefficients,
+            } => {
+                let travel_terms = travel_coefficients
+                    .iter()
+                    .rev()
+                    .fold(0., |sum, coefficient| sum * travel + coefficient);
+                let steer_terms = steer_coefficients
+                    .iter()
+                    .rev()
+                    .fold(0., |sum, coefficient| sum * steer + coefficient);
+                travel_terms + steer_terms * steer
+            }
+        }
+    }
+}
+
+// wheel alignment driven by suspension travel and steering, on the wheel joint. Camber (positive
+// top out), toe (positive toe-in) and track change (positive outwards) are applied to the wheel's
+// mounting transform before the kinematics pass. Steer dependent camber stands in for caster and
+// kingpin inclination. The curves take the travel from the suspension's ride height, so they
+// should be zero there; set the ride height to the static travel (with the static load as
+// preload) for a neutral static alignment.
+#[derive(Component)]
+pub struct SuspensionKinematics {
+    pub suspension: Entity,
+    pub steering: Option<Entity>,
+    side: f32, // 1 left, -1 right
+    camber: Option<KinematicCurve>,
+    toe: Option<KinematicCurve>,
+    track: Option<KinematicCurve>,
+    mount: Option<Xform>,    // the wheel's original mounting transform
+    pub alignment: [f32; 3], // camber, toe, track change
+}
+
+impl SuspensionKinematics {
+    pub fn new(suspension: Entity, steering: Option<Entity>, left: bool) -> Self {
+        Self {
+            suspension,
+            steering,
+            side: if left { 1. } else { -1. },
+            camber: None,
+            toe: None,
+            track: None,
+            mount: None,
+            alignment: [0., 0., 0.],
+        }
+    }
+
+    pub fn with_curves(
+        mut self,
+        camber: Option<KinematicCurve>,
+        toe: Option<KinematicCurve>,
+        track: Option<KinematicCurve>,
+    ) -> Self {
+        self.camber = camber;
+        self.toe = toe;
+        self.track = track;
+        self
+    }
+
+    pub fn from_def(
+        suspension_kinematics_def: &SuspensionKinematicsDef,
+        suspension: Entity,
+        steering: Option<Entity>,
+    ) -> Result<Self, String> {
+        let curve = |curve: &Option<KinematicCurveDef>, name: &str| {
+            curve
+                .as_ref()
+                .map(KinematicCurve::from_def)
+                .transpose()
+                .map_err(|e| format!("{} curve: {}", name, e))
+        };
+        Ok(
+            Self::new(suspension, steering, suspension_kinematics_def.left).with_curves(
+                curve(&suspension_kinematics_def.camber, "camber")?,
+                curve(&suspension_kinematics_def.toe, "toe")?,
+                curve(&suspension_kinematics_def.track, "track")?,
+            ),
+        )
+    }
+}
+
+// runs before loop_1, so the wheel transforms use this stage's travel and steer
+pub fn suspension_kinematics_system(
+    mut wheels: Query<(Entity, &mut SuspensionKinematics)>,
+    mut joints: Query<&mut Joint>,
+    suspensions: Query<&Suspension>,
+) {
+    for (wheel, mut kinematics) in wheels.iter_mut() {
+        let Ok(suspension) = joints.get(kinematics.suspension) else {
+            continue;
+        };
+        let ride_height = suspensions
+            .get(kinematics.suspension)
+            .map_or(0., |suspension| suspension.ride_height());
+        let travel = suspension.q - ride_height;
+        let steer = kinematics
+            .steering
+            .and_then(|steering| joints.get(steering).ok())
+            .map_or(0., |steering| steering.q);
+
+        let value = |curve: &Option<KinematicCurve>| {
+            curve
+                .as_ref()
+                .map_or(0., |curve| curve.value(travel, steer))
+        };
+        let alignment = [
+            value(&kinematics.camber),
+            value(&kinematics.toe),
+            value(&kinematics.track),
+        ];
+        kinematics.alignment = alignment;
+
+        let Ok(mut joint) = joints.get_mut(wheel) else {
+            continue;
+        };
+        let mount = *kinematics.mount.get_or_insert(joint.xt);
+        let side = kinematics.side;
+        joint.xt = Xform::rotx(-side * alignment[0])
+            * Xform::rotz(-side * alignment[1])
+            * Xform::posy(side * alignment[2])
+            * mount;
     }
 }
 
@@ -37,6 +382,9 @@ pub struct TireContact {
     damping: f32,
     longitudinal_stiffness: f32,
     lateral_stiffness: f32,
+    width: f32,
+    num_points: usize, // tread sections across the width
+    profile: f32,      // radius drop at the tread edge
 }
 
 impl TireContact {
@@ -53,9 +401,23 @@ impl TireContact {
             damping,
             longitudinal_stiffness,
             lateral_stiffness,
+            width: 0.,
+            num_points: 1,
+            profile: 0.,
         }
     }
 
+    // tire of finite width, sampled with num_points sections from edge to edge. The section
+    // radius drops by profile at the edges (parabolic crown), so the tire rolls onto its
+    // shoulder when cambered or on a kerb. With a profile the edge sections are less deflected,
+    // so the tire is softer and sits lower than the single point contact with the same stiffness.
+    pub fn with_width(mut self, width: f32, num_points: usize, profile: f32) -> Self {
+        self.width = width;
+        self.num_points = num_points.max(1);
+        self.profile = profile;
+        self
+    }
+
     pub fn from_def(tire_contact_def: &TireContactDef) -> Self {
         Self::new(
             tire_contact_def.radius,
@@ -64,39 +426,89 @@ impl TireContact {
             tire_contact_def.longitudinal_stiffness,
             tire_contact_def.lateral_stiffness,
         )
+        .with_width(
+            tire_contact_def.width,
+            tire_contact_def.num_points,
+            tire_contact_def.profile,
+        )
+    }
+
+    // contact patch of each tread section, each against the ground below it
+    fn sections(&self, joint: &Joint, terrain: &Terrain) -> Vec<ContactPatch> {
+        (0..self.num_points)
+            .map(|ind| {
+                if self.num_points == 1 {
+                    return ContactPatch::new(joint, self.radius, terrain);
+                }
+                let fraction = 2. * ind as f32 / (self.num_points - 1) as f32 - 1.; // -1 to 1
+                let radius = self.radius - self.profile * fraction * fraction;
+                ContactPatch::at_offset(joint, radius, fraction * self.width / 2., terrain)
+            })
+            .collect()
     }
 }
 
-// a very simple tire model. Not very realistic, but it works well enough for this demo.
-// it's also messy, but I/we can clean it up later
-pub fn tire_contact_system(mut joints: Query<(&mut Joint, &TireContact)>) {
-    for (mut joint, contact) in joints.iter_mut() {
+// below this forward speed the slip denominators are held constant, so the slip stays finite
+// when the car stops
+const LOW_SPEED: f32 = 1.;
+
+// slip of a wheel, iso sign conventions
+pub struct Slip {
+    pub kappa: f32, // slip ratio, positive when driving
+    pub alpha: f32, // slip angle (rad), positive when sliding left
+    pub gamma: f32, // camber (rad)
+    pub vx: f32,    // forward velocity of the wheel center
+}
+
+// the contact frame of a wheel on the ground, all in absolute coordinates
+pub struct ContactPatch {
+    pub point: Vector,    // contact point
+    pub forward: Vector,  // x axis of the contact reference frame
+    pub lat: Vector,      // y axis of the contact reference frame
+    pub up: Vector,       // z axis of the contact reference frame (ground normal)
+    pub tire_lat: Vector, // axis of tire rotation
+    pub tire_up: Vector,  // from the contact point to the wheel center
+    pub height: f32,      // distance from the wheel center to the contact point (loaded radius)
+    pub deflection: f32,
+    pub velocity: Vector, // velocity of the wheel body at the contact point
+    pub surface: SurfacePoint,
+}
+
+impl ContactPatch {
+    pub fn new(joint: &Joint, radius: f32, terrain: &Terrain) -> Self {
+        Self::at_offset(joint, radius, 0., terrain)
+    }
+
+    // for a disc offset along the axis of rotation (a section of a wide tire)
+    pub fn at_offset(joint: &Joint, radius: f32, offset: f32, terrain: &Terrain) -> Self {
         // x0 is the inverse of the joint transform (used several times later)
         let x0 = joint.x.inverse();
 
         // we care about two reference frames: the contact and the tire
         // the y axis of the tire reference frame is the axis of rotation of the tire
-        // the y axis of the contact reference frame is the axis of rotation of the tire projected onto the ground (no z component)
-        // the z axis of the contact reference frame is the up vector in absolute coordinates
+        // the y axis of the contact reference frame is the axis of rotation of the tire projected onto the ground plane
+        // the z axis of the contact reference frame is the ground normal in absolute coordinates
         // the x axis of the contact reference frame is the cross product of the y and z axes
         // the x axis of the tire reference frame is the same as the x axis of the contact reference frame
         // the z axis of the tire reference frame is the cross product of the x and y axes
 
         // each of the reference frames can be written in absolute coordinates or local coordinates
         // wheel center in absolute coordinates
-        let tire_point_abs = x0.transform_point(Vector::new(0., 0., 0.)); // wheel center in absolute coordinates
+        let tire_point_abs = x0.transform_point(Vector::new(0., offset, 0.)); // wheel center in absolute coordinates
+
+        // the ground below the wheel center, treated as a plane
+        let surface = terrain.surface_at(tire_point_abs);
 
         // y axis of tire reference frame in local coordinates
         let tire_lat_local = Vector::new(0., 1., 0.); // axis of tire rotation
 
+        // z axis of contact reference frame in absolute coordinates
+        let contact_up_abs = surface.normal; // ground normal in absolute coordinates
+
         // y axis of contact reference frame in absolute coordinates
         let tire_lat_abs = x0 * tire_lat_local;
-        let mut contact_lat_abs = tire_lat_abs;
-        contact_lat_abs.z = 0.; // axis of tire rotation projected onto the ground (no z component)
-        let contact_lat_abs = contact_lat_abs.normalize();
-
-        // z axis of contact reference frame in absolute coordinates
-        let contact_up_abs = Vector::new(0., 0., 1.); // up vector in absolute coordinates
+        let contact_lat_abs =
+            (tire_lat_abs - tire_lat_abs.dot(&contact_up_abs) * contact_up_abs).normalize(); // axis of tire rotation projected onto the ground plane
 
         // x axis of contact reference frame in absolute coordinates
         let contact_forward_abs = contact_lat_abs.cross(&contact_up_abs).normalize();
@@ -105,39 +517,757 @@ pub fn tire_contact_system(mut joints: Query<(&mut Joint, &TireContact)>) {
         let tire_up_abs = contact_forward_abs.cross(&tire_lat_abs).normalize();
 
         // contact point in absolute coordinates
-        let height = tire_point_abs.z / tire_up_abs.z; // height of the wheel center along the tire up vector
+        let height = (tire_point_abs - surface.point).dot(&contact_up_abs)
+            / tire_up_abs.dot(&contact_up_abs); // height of the wheel center along the tire up vector
         let contact_point_abs = tire_point_abs - height * tire_up_abs; // subtract the height from the wheel center to get the contact point
-        let deflection = contact.radius - height; // deflection of the tire
+        let deflection = radius - height; // deflection of the tire
 
-        if deflection > 0. {
-            // vertical forces
-            let spring_force = contact.stiffness * deflection;
+        let v0 = x0 * joint.v;
+        let vel_abs = v0.velocity_point(contact_point_abs);
+
+        Self {
+            point: contact_point_abs,
+            forward: contact_forward_abs,
+            lat: contact_lat_abs,
+            up: contact_up_abs,
+            tire_lat: tire_lat_abs,
+            tire_up: tire_up_abs,
+            height,
+            deflection,
+            velocity: vel_abs.vel,
+            surface: SurfacePoint {
+                point: contact_point_abs,
+                ..surface
+            },
+        }
+    }
+
+    // slip from the wheel spin and the velocity of the wheel center in the contact plane.
+    // The contact point velocity already includes the spin, so take the center velocity.
+    pub fn slip(&self, wheel_speed: f32) -> Slip {
+        let center_velocity = self.velocity + wheel_speed * self.height * self.forward;
+        let vx = center_velocity.dot(&self.forward);
+        let vy = center_velocity.dot(&self.lat);
+        let denominator = vx.abs().max(LOW_SPEED);
+        Slip {
+            kappa: (wheel_speed * self.height - vx) / denominator,
+            alpha: vy.atan2(denominator),
+            gamma: self.tire_lat.dot(&self.up).clamp(-1., 1.).asin(),
+            vx,
+        }
+    }
+
+    // power dissipated by ground plane forces sliding at the contact point
+    pub fn slip_power(&self, forward: f32, lat: f32) -> f32 {
+        (forward * self.velocity.dot(&self.forward)).abs()
+            + (lat * self.velocity.dot(&self.lat)).abs()
+    }
+
+    // forward, lateral and vertical force (contact frame) at the contact point, plus a moment
+    // about the ground normal
+    pub fn force(&self, forward: f32, lat: f32, vertical: f32, moment: f32) -> Force {
+        let mut force = Force::force_point(
+            forward * self.forward + lat * self.lat + vertical * self.up,
+            self.point,
+        );
+        force.m += moment * self.up;
+        force
+    }
+}
+
+// wheel joint aux states (Joint::aux)
+pub(super) const TREAD_STATES: [usize; 2] = [0, 1]; // longitudinal, lateral tread deflection
+pub(super) const PAD_STATE: usize = 2; // brake pad spring deflection (Brake)
+
+// off the ground the tread springs back to zero deflection with this time constant
+const TREAD_RELEASE_TIME: f32 = 0.01;
+
+// first order slip relaxation for TireContact, replaces its velocity based ground plane forces.
+// The tread deflection u follows du/dt = -slip velocity - |vx| / relaxation length * u, and the
+// force is proportional to the deflection, so a stopped car is held by the deflected tread
+// instead of jittering. The deflection is part of the wheel joint state (TREAD_STATES), so it is
+// integrated by the same solver as the joints.
+// A wide TireContact has a single tread deflection, driven by the slip and friction limit of its
+// most deflected section; every section in contact uses that deflection for its force.
+// Stiffness and damping are per unit vertical force (the same as TireContact).
+#[derive(Component)]
+pub struct TireRelaxation {
+    longitudinal_length: f32,
+    lateral_length: f32,
+    longitudinal_stiffness: f32,
+    lateral_stiffness: f32,
+    longitudinal_damping: f32,
+    lateral_damping: f32,
+}
+
+impl TireRelaxation {
+    pub fn new(
+        longitudinal_length: f32,
+        lateral_length: f32,
+        longitudinal_stiffness: f32,
+        lateral_stiffness: f32,
+        longitudinal_damping: f32,
+        lateral_damping: f32,
+    ) -> Self {
+        Self {
+            longitudinal_length,
+            lateral_length,
+            longitudinal_stiffness,
+            lateral_stiffness,
+            longitudinal_damping,
+            lateral_damping,
+        }
+    }
+
+    pub fn from_def(tire_relaxation_def: &TireRelaxationDef) -> Self {
+        Self::new(
+            tire_relaxation_def.longitudinal_length,
+            tire_relaxation_def.lateral_length,
+            tire_relaxation_def.longitudinal_stiffness,
+            tire_relaxation_def.lateral_stiffness,
+            tire_relaxation_def.longitudinal_damping,
+            tire_relaxation_def.lateral_damping,
+        )
+    }
+
+    // (longitudinal, lateral) tread deflection of a wheel joint
+    pub fn deflection(joint: &Joint) -> [f32; 2] {
+        TREAD_STATES.map(|state| joint.aux[state])
+    }
+
+    // rate of change of the (longitudinal, lateral) deflection
+    fn deflection_rate(
+        &self,
+        deflection: [f32; 2],
+        slip_velocity: [f32; 2],
+        rolling_speed: f32,
+    ) -> [f32; 2] {
+        [
+            -slip_velocity[0] - rolling_speed.abs() / self.longitudinal_length * deflection[0],
+            -slip_velocity[1] - rolling_speed.abs() / self.lateral_length * deflection[1],
+        ]
+    }
+
+    // (longitudinal, lateral) force per unit vertical force
+    fn force(&self, deflection: [f32; 2], slip_velocity: [f32; 2], rolling_speed: f32) -> [f32; 2] {
+        let rate = self.deflection_rate(deflection, slip_velocity, rolling_speed);
+        [
+            self.longitudinal_stiffness * deflection[0] + self.longitudinal_damping * rate[0],
+            self.lateral_stiffness * deflection[1] + self.lateral_damping * rate[1],
+        ]
+    }
+
+    // deflection rates for the joint state. The deflection does not grow past what friction can
+    // hold (friction scale with the tire grip, per unit vertical force), so it does not wind up
+    // while sliding, and it springs back off the ground.
+    fn state_rates(
+        &self,
+        deflection: [f32; 2],
+        contact: Option<([f32; 2], f32, f32)>, // slip velocity, rolling speed, friction scale
+    ) -> [f32; 2] {
+        let Some((slip_velocity, rolling_speed, friction_scale)) = contact else {
+            return deflection.map(|u| -u / TREAD_RELEASE_TIME);
+        };
+        let rates = self.deflection_rate(deflection, slip_velocity, rolling_speed);
+        let limits = [
+            friction_scale / self.longitudinal_stiffness,
+            friction_scale / self.lateral_stiffness,
+        ];
+        [0, 1].map(|i| {
+            let (u, limit) = (deflection[i], limits[i]);
+            if u.abs() >= limit && rates[i] * u > 0. {
+                (u.clamp(-limit, limit) - u) / TREAD_RELEASE_TIME
+            } else {
+                rates[i]
+            }
+        })
+    }
+}
 
-            let v0 = x0 * joint.v;
-            let vel_abs = v0.velocity_point(contact_point_abs);
-            let damping_force = -contact.damping * tire_up_abs.dot(&vel_abs.vel);
+// what the tire system computed in the last stage, for telemetry. Add to a wheel with a tire
+// component. Wide tires report the sum of the forces over the tread sections, the largest
+// deflection and the load weighted contact point and slip velocities.
+#[derive(Component, Default, Debug, Clone)]
+pub struct TireState {
+    pub in_contact: bool,
+    pub deflection: f32,
+    pub load: f32, // vertical force
+    pub forward_slip_velocity: f32,
+    pub lateral_slip_velocity: f32,
+    pub forward_force: f32,
+    pub lateral_force: f32,
+    pub aligning_moment: f32,
+    pub contact_point: Vector, // absolute coordinates
+    weight: f32,               // sum of the averaging weights (load clamped at zero)
+}
+
+impl TireState {
+    pub(super) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    // adds a tread section (or the whole tire)
+    pub(super) fn add(
+        &mut self,
+        patch: &ContactPatch,
+        load: f32,
+        forward_force: f32,
+        lateral_force: f32,
+        aligning_moment: f32,
+    ) {
+        let weight = load.max(0.);
+        self.in_contact = true;
+        self.deflection = self.deflection.max(patch.deflection);
+        self.load += load;
+        self.forward_slip_velocity += weight * patch.velocity.dot(&patch.forward);
+        self.lateral_slip_velocity += weight * patch.velocity.dot(&patch.lat);
+        self.forward_force += forward_force;
+        self.lateral_force += lateral_force;
+        self.aligning_moment += aligning_moment;
+        self.contact_point += weight * patch.point;
+        self.weight += weight;
+    }
+
+    // turns the load weighted sums into averages
+    pub(super) fn finish(&mut self) {
+        if self.weight > 0. {
+            self.forward_slip_velocity /= self.weight;
+            self.lateral_slip_velocity /= self.weight;
+            self.contact_point /= self.weight;
+        }
+    }
+}
+
+// rolling resistance, tread temperature and wear of a tire. Add to a wheel with a tire
+// component; the tire system records the load and slip power every stage, and
+// tire_condition_system integrates the temperature and wear once per step.
+#[derive(Component)]
+pub struct TireCondition {
+    rolling_resistance: f32,       // coefficient at zero speed
+    rolling_resistance_speed: f32, // increase per speed squared (s^2/m^2)
+    heat_capacity: f32,            // J/K of the tread
+    cooling: f32,                  // W/K at standstill
+    cooling_speed: f32,            // additional W/K per m/s
+    heating_fraction: f32,         // fraction of the slip power that heats the tread
+    ambient_temperature: f32,
+    optimal_temperature: f32,
+    temperature_window: f32, // grip drops by thermal_grip_loss this far from the optimum
+    thermal_grip_loss: f32,
+    wear_rate: f32,       // wear per joule of slip energy, 1 is fully worn
+    worn_grip_loss: f32,  // grip lost when fully worn
+    pub temperature: f32, // tread temperature
+    pub wear: f32,
+    pub load: f32,                      // vertical force
+    pub slip_power: f32,                // power dissipated by sliding
+    pub rolling_resistance_torque: f32, // about the wheel axis, opposing the spin
+    speed: f32,                         // tread speed, for the cooling
+}
+
+impl TireCondition {
+    // rolling resistance only, the temperature and wear do not change the grip until they are
+    // set up with the with_* methods
+    pub fn new(rolling_resistance: f32, rolling_resistance_speed: f32) -> Self {
+        Self {
+            rolling_resistance,
+            rolling_resistance_speed,
+            heat_capacity: 1.,
+            cooling: 1.,
+            cooling_speed: 0.,
+            heating_fraction: 0.,
+            ambient_temperature: 25.,
+            optimal_temperature: 25.,
+            temperature_window: 1.,
+            thermal_grip_loss: 0.,
+            wear_rate: 0.,
+            worn_grip_loss: 0.,
+            temperature: 25.,
+            wear: 0.,
+            load: 0.,
+            slip_power: 0.,
+            rolling_resistance_torque: 0.,
+            speed: 0.,
+        }
+    }
+
+    // tread heat balance. The tread starts at the ambient temperature.
+    pub fn with_heating(
+        mut self,
+        heat_capacity: f32,
+        cooling: f32,
+        cooling_speed: f32,
+        heating_fraction: f32,
+        ambient_temperature: f32,
+    ) -> Self {
+        self.heat_capacity = heat_capacity;
+        self.cooling = cooling;
+        self.cooling_speed = cooling_speed;
+        self.heating_fraction = heating_fraction;
+        self.ambient_temperature = ambient_temperature;
+        self.temperature = ambient_temperature;
+        self
+    }
+
+    // grip drops by grip_loss at window away from the optimal temperature
+    pub fn with_thermal_grip(
+        mut self,
+        optimal_temperature: f32,
+        window: f32,
+        grip_loss: f32,
+    ) -> Self {
+        self.optimal_temperature = optimal_temperature;
+        self.temperature_window = window;
+        self.thermal_grip_loss = grip_loss;
+        self
+    }
+
+    pub fn with_wear(mut self, wear_rate: f32, worn_grip_loss: f32) -> Self {
+        self.wear_rate = wear_rate;
+        self.worn_grip_loss = worn_grip_loss;
+        self
+    }
+
+    // e.g. a pre-heated tire, call after with_heating
+    pub fn with_initial_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn from_def(tire_condition_def: &TireConditionDef) -> Self {
+        let condition = Self::new(
+            tire_condition_def.rolling_resistance,
+            tire_condition_def.rolling_resistance_speed,
+        )
+        .with_heating(
+            tire_condition_def.heat_capacity,
+            tire_condition_def.cooling,
+            tire_condition_def.cooling_speed,
+            tire_condition_def.heating_fraction,
+            tire_condition_def.ambient_temperature,
+        )
+        .with_thermal_grip(
+            tire_condition_def.optimal_temperature,
+            tire_condition_def.temperature_window,
+            tire_condition_def.thermal_grip_loss,
+        )
+        .with_wear(
+            tire_condition_def.wear_rate,
+            tire_condition_def.worn_grip_loss,
+        );
+        match tire_condition_def.initial_temperature {
+            Some(temperature) => condition.with_initial_temperature(temperature),
+            None => condition,
+        }
+    }
+
+    // friction multiplier from temperature and wear
+    pub fn grip(&self) -> f32 {
+        let offset = ((self.temperature - self.optimal_temperature) / self.temperature_window)
+            .powi(2)
+            .min(1.);
+        (1. - self.thermal_grip_loss * offset) * (1. - self.worn_grip_loss * self.wear)
+    }
+
+    pub(super) fn clear_contact(&mut self) {
+        self.load = 0.;
+        self.slip_power = 0.;
+        self.rolling_resistance_torque = 0.;
+    }
+
+    // records the contact and applies the rolling resistance torque to the wheel, acting at the
+    // loaded radius (wheel center to contact point). The surface scales the rolling resistance
+    // coefficient.
+    pub(super) fn contact(
+        &mut self,
+        joint: &mut Joint,
+        load: f32,
+        radius: f32,
+        slip_power: f32,
+        rolling_resistance_scale: f32,
+    ) {
+        let speed = joint.qd * radius;
+        let coefficient = rolling_resistance_scale
+            * (self.rolling_resistance + self.rolling_resistance_speed * speed * speed);
+        self.load = load;
+        self.slip_power = slip_power;
+        self.speed = speed.abs();
+        self.rolling_resistance_torque = coefficient * load * radius * speed.clamp(-1., 1.);
+        joint.tau -= self.rolling_resistance_torque;
+    }
+}
+
+impl RollbackState for TireCondition {
+    type State = [f32; 2];
+    fn save(&self) -> Self::State {
+        [self.temperature, self.wear]
+    }
+    fn restore(&mut self, state: &Self::State) {
+        [self.temperature, self.wear] = *state;
+    }
+}
+
+// a very simple tire model. Not very realistic, but it works well enough for this demo.
+// it's also messy, but I/we can clean it up later
+#[allow(clippy::type_complexity)]
+pub fn tire_contact_system(
+    mut joints: Query<(
+        &mut Joint,
+        &TireContact,
+        Option<&TireRelaxation>,
+        Option<&mut TireCondition>,
+        Option<&mut TireState>,
+    )>,
+    terrain: Res<Terrain>,
+    surfaces: Res<SurfaceMap>,
+) {
+    for (mut joint, contact, relaxation, mut condition, mut state) in joints.iter_mut() {
+        if let Some(state) = state.as_mut() {
+            state.clear();
+        }
+        let grip = condition.as_ref().map_or(1., |condition| condition.grip());
+        let deflection = TireRelaxation::deflection(&joint);
+        let mut load = 0.;
+        let mut slip_power = 0.;
+        // load weighted sum over the sections, the load clamped at zero
+        let mut rolling_resistance_scale = 0.;
+        let mut loaded_radius = 0.;
+        let mut rolling_resistance_weight = 0.;
+        // most deflected section: (deflection, slip velocity, rolling speed, friction scale with
+        // the tire grip, the same as the force limit)
+        let mut deepest: Option<(f32, [f32; 2], f32, f32)> = None;
+
+        // each tread section carries its share of the stiffness and has its own friction limit
+        let share = 1. / contact.num_points as f32;
+        for patch in contact.sections(&joint, &terrain) {
+            if patch.deflection <= 0. {
+                continue;
+            }
+
+            // vertical forces
+            let spring_force = share * contact.stiffness * patch.deflection;
+            let damping_force = -share * contact.damping * patch.tire_up.dot(&patch.velocity);
             let vertical_force = spring_force + damping_force;
 
             // ground plane forces
-            let forward_vel = vel_abs.vel.dot(&contact_forward_abs); // component of velocity in the forward direction
-            let lat_vel = vel_abs.vel.dot(&contact_lat_abs); // component of velocity in the lateral direction
-            let mut forward_force = -forward_vel * contact.longitudinal_stiffness * vertical_force;
-            let mut lat_force = -lat_vel * contact.lateral_stiffness * vertical_force;
-
-            forward_force = forward_force.max(-vertical_force).min(vertical_force);
-            lat_force = lat_force.max(-vertical_force).min(vertical_force);
-
-            let f_abs = Force::force_point(
-                forward_force * contact_forward_abs
-                    + lat_force * contact_lat_abs
-                    + Vector::new(0., 0., vertical_force),
-                contact_point_abs,
+            let forward_vel = patch.velocity.dot(&patch.forward); // component of velocity in the forward direction
+            let lat_vel = patch.velocity.dot(&patch.lat); // component of velocity in the lateral direction
+            let rolling_speed = patch.slip(joint.qd).vx;
+            let (mut forward_force, mut lat_force) = match relaxation {
+                Some(relaxation) => {
+                    let [forward, lat] =
+                        relaxation.force(deflection, [forward_vel, lat_vel], rolling_speed);
+                    (forward * vertical_force, lat * vertical_force)
+                }
+                None => (
+                    -forward_vel * contact.longitudinal_stiffness * vertical_force,
+                    -lat_vel * contact.lateral_stiffness * vertical_force,
+                ),
+            };
+
+            // ground material at the contact point scales the friction limit
+            let material = surfaces.material_at(&patch.surface);
+            if deepest.map_or(true, |(depth, ..)| patch.deflection > depth) {
+                deepest = Some((
+                    patch.deflection,
+                    [forward_vel, lat_vel],
+                    rolling_speed,
+                    grip * material.friction_scale,
+                ));
+            }
+            let friction_limit = grip * material.friction_scale * vertical_force;
+            forward_force = forward_force.max(-friction_limit).min(friction_limit);
+            lat_force = lat_force.max(-friction_limit).min(friction_limit);
+            load += vertical_force;
+            slip_power += patch.slip_power(forward_force, lat_force);
+            rolling_resistance_scale += material.rolling_resistance_scale * vertical_force.max(0.);
+            loaded_radius += patch.height * vertical_force.max(0.);
+            rolling_resistance_weight += vertical_force.max(0.);
+
+            joint.f_ext += patch.force(forward_force, lat_force, vertical_force, 0.);
+            if let Some(state) = state.as_mut() {
+                state.add(&patch, vertical_force, forward_force, lat_force, 0.);
+            }
+        }
+
+        if let Some(relaxation) = relaxation {
+            let contact = deepest.map(|(_, slip_velocity, rolling_speed, friction_scale)| {
+                (slip_velocity, rolling_speed, friction_scale)
+            });
+            let rates = relaxation.state_rates(deflection, contact);
+            for (state, rate) in TREAD_STATES.into_iter().zip(rates) {
+                joint.aux_d[state] = rate;
+            }
+        }
+        if let Some(condition) = condition.as_mut() {
+            let (rolling_resistance_scale, loaded_radius) = if rolling_resistance_weight > 0. {
+                (
+                    rolling_resistance_scale / rolling_resistance_weight,
+                    loaded_radius / rolling_resistance_weight,
+                )
+            } else {
+                (1., contact.radius)
+            };
+            condition.contact(
+                &mut joint,
+                load,
+                loaded_radius,
+                slip_power,
+                rolling_resistance_scale,
+            );
+        }
+        if let Some(state) = state.as_mut() {
+            state.finish();
+        }
+    }
+}
+
+// measured fx and fy over slip ratio, slip angle, vertical force, camber and pressure
+pub struct TireTable {
+    fx: LookupTable,
+    fy: LookupTable,
+}
+
+impl TireTable {
+    // inputs of the lookup, in table axis order
+    const INPUTS: [&'static str; 5] = ["SR", "SA", "FZ", "IA", "P"];
+    const MAX_INFERRED_BREAKPOINTS: usize = 16;
+
+    // csv with a header row using the ttc channel names: SR, SA (deg), FZ (N), IA (deg),
+    // optionally P (kPa), FX and FY (N). Other columns and non numeric rows (units) are
+    // ignored, a data row with a missing or non numeric value is an error. The rows do not have to lie on a grid, they are fitted onto breakpoints for each
+    // input (in the csv units and order of INPUTS). Inputs without breakpoints use the distinct
+    // values in the data, so binned data is reproduced exactly.
+    pub fn from_csv(
+        path: impl AsRef<Path>,
+        breakpoints: &[Option<Vec<f32>>; 5],
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        // (line number, line), so row errors can point at the line
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line))
+            .filter(|(_, line)| !line.trim().is_empty());
+        let header: Vec<String> = lines
+            .next()
+            .ok_or(format!("{}: empty file", path.display()))?
+            .1
+            .split([',', '\t'])
+            .map(|name| name.trim().to_uppercase())
+            .collect();
+        let column = |name: &str| header.iter().position(|column| column == name);
+        let required =
+            |name: &str| column(name).ok_or(format!("{}: missing column {}", path.display(), name));
+        let input_columns = [
+            Some(required("SR")?),
+            Some(required("SA")?),
+            Some(required("FZ")?),
+            Some(required("IA")?),
+            column("P"),
+        ];
+        let (fx_column, fy_column) = (required("FX")?, required("FY")?);
+
+        // ttc data is in sae axes (z down), convert to the iso axes used here
+        let convert: [fn(f32) -> f32; 5] = [
+            |sr: f32| sr,
+            |sa: f32| -sa.to_radians(),
+            |fz: f32| fz.abs(),
+            |ia: f32| ia.to_radians(),
+            |p: f32| p,
+        ];
+        let mut fx_samples = Vec::new();
+        let mut fy_samples = Vec::new();
+        for (line_number, line) in lines {
+            let fields: Vec<Result<f32, _>> = line
+                .split([',', '\t'])
+                .map(|value| value.trim().parse::<f32>())
+                .collect();
+            if fields.iter().all(|field| field.is_err()) {
+                continue; // units or labels
+            }
+            if fields.len() != header.len() || fields.iter().any(|field| field.is_err()) {
+                return Err(format!(
+                    "{}:{}: expected {} numeric columns like the header",
+                    path.display(),
+                    line_number,
+                    header.len()
+                ));
+            }
+            let row: Vec<f32> = fields.into_iter().flatten().collect();
+            let value = |column: usize| row[column];
+            let inputs: Vec<f32> = input_columns
+                .iter()
+                .zip(convert)
+                .map(|(column, convert)| convert(column.map_or(0., value)))
+                .collect();
+            let (fx, fy) = (value(fx_column), -value(fy_column));
+            if inputs.iter().chain([&fx, &fy]).all(|x| x.is_finite()) {
+                fx_samples.push((inputs.clone(), fx));
+                fy_samples.push((inputs, fy));
+            }
+        }
+
+        // given breakpoints, otherwise the distinct values of the input, or evenly spaced ones
+        // over its range when there are too many (scattered data)
+        let axes: Vec<Vec<f32>> = (0..Self::INPUTS.len())
+            .map(|input| {
+                let mut axis: Vec<f32> = match &breakpoints[input] {
+                    Some(breakpoints) => breakpoints.iter().map(|x| convert[input](*x)).collect(),
+                    None => fx_samples.iter().map(|(inputs, _)| inputs[input]).collect(),
+                };
+                axis.sort_by(|a, b| a.total_cmp(b));
+                axis.dedup();
+                if breakpoints[input].is_none() && axis.len() > Self::MAX_INFERRED_BREAKPOINTS {
+                    let (min, max) = (axis[0], axis[axis.len() - 1]);
+                    let step = (max - min) / (Self::MAX_INFERRED_BREAKPOINTS - 1) as f32;
+                    axis = (0..Self::MAX_INFERRED_BREAKPOINTS)
+                        .map(|i| min + i as f32 * step)
+                        .collect();
+                }
+                axis
+            })
+            .collect();
+        if let Some(input) = axes.iter().position(|axis| axis.is_empty()) {
+            return Err(format!(
+                "{}: no {} breakpoints",
+                path.display(),
+                Self::INPUTS[input]
+            ));
+        }
+
+        let fit = |samples| {
+            LookupTable::fit(axes.clone(), samples)
+                .map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        Ok(Self {
+            fx: fit(&fx_samples)?,
+            fy: fit(&fy_samples)?,
+        })
+    }
+    pub fn forces(&self, kappa: f32, alpha: f32, fz: f32, gamma: f32, pressure: f32) -> (f32, f32) {
+        let point = [kappa, alpha, fz, gamma, pressure];
+        (self.fx.interpolate(&point), self.fy.interpolate(&point))
+    }
+}
+
+// tire with ground plane forces from measured data. Use instead of TireContact.
+#[derive(Component)]
+pub struct TableTire {
+    radius: f32,
+    stiffness: f32,
+    damping: f32,
+    table: TireTable,
+    pub pressure: f32, // kPa
+}
+
+impl TableTire {
+    pub fn new(radius: f32, stiffness: f32, damping: f32, table: TireTable, pressure: f32) -> Self {
+        Self {
+            radius,
+            stiffness,
+            damping,
+            table,
+            pressure,
+        }
+    }
+
+    pub fn from_def(table_tire_def: &TableTireDef) -> Result<Self, String> {
+        Ok(Self::new(
+            table_tire_def.radius,
+            table_tire_def.stiffness,
+            table_tire_def.damping,
+            TireTable::from_csv(
+                &table_tire_def.csv_file,
+                &table_tire_def
+                    .breakpoints
+                    .as_ref()
+                    .map_or(Default::default(), |breakpoints| breakpoints.inputs()),
+            )?,
+            table_tire_def.pressure,
+        ))
+    }
+}
+
+pub fn table_tire_system(
+    mut joints: Query<(
+        &mut Joint,
+        &TableTire,
+        Option<&mut TireCondition>,
+        Option<&mut TireState>,
+    )>,
+    terrain: Res<Terrain>,
+    surfaces: Res<SurfaceMap>,
+) {
+    for (mut joint, tire, mut condition, mut state) in joints.iter_mut() {
+        if let Some(condition) = condition.as_mut() {
+            condition.clear_contact();
+        }
+        if let Some(state) = state.as_mut() {
+            state.clear();
+        }
+        let patch = ContactPatch::new(&joint, tire.radius, &terrain);
+        if patch.deflection <= 0. {
+            continue;
+        }
+
+        // vertical
+        let spring_force = tire.stiffness * patch.deflection;
+        let damping_force = -tire.damping * patch.tire_up.dot(&patch.velocity);
+        let fz = (spring_force + damping_force).max(0.);
+
+        let slip = patch.slip(joint.qd);
+        let (fx, fy) = tire
+            .table
+            .forces(slip.kappa, slip.alpha, fz, slip.gamma, tire.pressure);
+
+        // the measured friction is scaled by the ground material and the tire condition
+        let material = surfaces.material_at(&patch.surface);
+        let grip = condition.as_ref().map_or(1., |condition| condition.grip());
+        let fx = grip * material.friction_scale * fx;
+        let fy = grip * material.friction_scale * fy;
+        if let Some(condition) = condition.as_mut() {
+            let slip_power = patch.slip_power(fx, fy);
+            let rolling_resistance_scale = material.rolling_resistance_scale;
+            condition.contact(
+                &mut joint,
+                fz,
+                patch.height,
+                slip_power,
+                rolling_resistance_scale,
             );
-            joint.f_ext += f_abs;
+        }
+
+        joint.f_ext += patch.force(fx, fy, fz, 0.);
+        if let Some(state) = state.as_mut() {
+            state.add(&patch, fz, fx, fy, 0.);
+            state.finish();
         }
     }
 }
 
+// tread temperature (implicit in the cooling) and wear, once per step
+pub fn tire_condition_system(
+    mut joints: Query<(&Joint, &mut TireCondition)>,
+    fixed_time: Res<FixedTime>,
+) {
+    let dt = fixed_time.period.as_secs_f32();
+    for (joint, mut condition) in joints.iter_mut() {
+        let heating = condition.heating_fraction * condition.slip_power
+            + (condition.rolling_resistance_torque * joint.qd).abs();
+        let cooling = condition.cooling + condition.cooling_speed * condition.speed;
+        condition.temperature = (condition.temperature
+            + dt / condition.heat_capacity * (heating + cooling * condition.ambient_temperature))
+            / (1. + dt * cooling / condition.heat_capacity);
+
+        let wear = condition.wear + condition.wear_rate * condition.slip_power * dt;
+        condition.wear = wear.min(1.);
+    }
+}
+
 #[derive(Component)]
 pub struct Steering {
     pub max_angle: f32,
@@ -159,11 +1289,141 @@ pub fn steering_system(mut joints: Query<(&mut Joint, &Steering)>, control: Res<
     }
 }
 
+// steering rack driving a left and right steer joint, on its own entity. The handwheel turns
+// the rack through the steering ratio, the rack turns each wheel through its steering arm,
+// and the ackermann fraction blends from parallel (0) to ideal ackermann (1) steering.
+// With a column stiffness the steer joints are driven by a torsion spring to their target
+// angle instead of being set, so the tire aligning torque feeds back into column_torque.
+#[derive(Component)]
+pub struct SteeringRack {
+    pub left: Entity,
+    pub right: Entity,
+    steering_ratio: f32, // handwheel angle / road wheel angle, at small angles
+    max_handwheel_angle: f32,
+    arm_length: f32, // steering arm, rack travel to wheel angle
+    max_rack_travel: f32,
+    wheelbase: f32,
+    track: f32,
+    ackermann: f32,
+    toe: [f32; 2],              // left, right (rad, positive toe-in)
+    column: Option<[f32; 2]>,   // stiffness (Nm/rad at the wheels) and damping
+    pub handwheel_angle: f32,   // rad
+    pub rack_travel: f32,       // m
+    pub wheel_angles: [f32; 2], // left, right target angles
+    pub column_torque: f32,     // aligning torque at the handwheel
+}
+
+impl SteeringRack {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        left: Entity,
+        right: Entity,
+        steering_ratio: f32,
+        max_handwheel_angle: f32,
+        arm_length: f32,
+        max_rack_travel: f32,
+        wheelbase: f32,
+        track: f32,
+        ackermann: f32,
+        toe: [f32; 2],
+    ) -> Self {
+        Self {
+            left,
+            right,
+            steering_ratio,
+            max_handwheel_angle,
+            arm_length,
+            max_rack_travel,
+            wheelbase,
+            track,
+            ackermann,
+            toe,
+            column: None,
+            handwheel_angle: 0.,
+            rack_travel: 0.,
+            wheel_angles: [0., 0.],
+            column_torque: 0.,
+        }
+    }
+
+    pub fn with_column(mut self, stiffness: f32, damping: f32) -> Self {
+        self.column = Some([stiffness, damping]);
+        self
+    }
+
+    pub fn from_def(steering_rack_def: &SteeringRackDef, left: Entity, right: Entity) -> Self {
+        let rack = Self::new(
+            left,
+            right,
+            steering_rack_def.steering_ratio,
+            steering_rack_def.max_handwheel_angle,
+            steering_rack_def.arm_length,
+            steering_rack_def.max_rack_travel,
+            steering_rack_def.wheelbase,
+            steering_rack_def.track,
+            steering_rack_def.ackermann,
+            [steering_rack_def.toe_left, steering_rack_def.toe_right],
+        );
+        match steering_rack_def.column_stiffness {
+            Some(stiffness) => rack.with_column(stiffness, steering_rack_def.column_damping),
+            None => rack,
+        }
+    }
+
+    // left and right wheel angles (positive turns left) for a steering input in -1 to 1
+    fn update(&mut self, steering: f32) -> [f32; 2] {
+        self.handwheel_angle = steering * self.max_handwheel_angle;
+        self.rack_travel = (self.arm_length * self.handwheel_angle / self.steering_ratio)
+            .clamp(-self.max_rack_travel, self.max_rack_travel);
+        let parallel = (self.rack_travel / self.arm_length).clamp(-1., 1.).asin();
+
+        // ideal ackermann, the wheel axes meet on the rear axle line
+        let tan = parallel.tan();
+        let half_track = self.track / 2.;
+        let ideal_left = (self.wheelbase * tan).atan2(self.wheelbase - half_track * tan);
+        let ideal_right = (self.wheelbase * tan).atan2(self.wheelbase + half_track * tan);
+        let left = parallel + self.ackermann * (ideal_left - parallel);
+        let right = parallel + self.ackermann * (ideal_right - parallel);
+
+        // toe-in turns the left wheel right and the right wheel left
+        self.wheel_angles = [left - self.toe[0], right + self.toe[1]];
+        self.wheel_angles
+    }
+}
+
+pub fn steering_rack_system(
+    mut racks: Query<&mut SteeringRack>,
+    mut joints: Query<&mut Joint>,
+    control: Res<CarControl>,
+) {
+    for mut rack in racks.iter_mut() {
+        let angles = rack.update(control.steering);
+        let column = rack.column;
+        let mut wheel_torque = 0.;
+        for (entity, angle) in [(rack.left, angles[0]), (rack.right, angles[1])] {
+            let Ok(mut joint) = joints.get_mut(entity) else {
+                continue;
+            };
+            match column {
+                Some([stiffness, damping]) => {
+                    let torque = stiffness * (angle - joint.q) - damping * joint.qd;
+                    joint.tau += torque;
+                    wheel_torque += torque;
+                }
+                None => joint.q = angle,
+            }
+        }
+        // the torque twisting the column, felt at the handwheel through the ratio
+        rack.column_torque = wheel_torque / rack.steering_ratio;
+    }
+}
+
 #[derive(Component)]
 pub struct DrivenWheel {
     pub max_torque: f32,
     pub max_speed: f32,
     pub max_power: f32,
+    pub throttle_scale: f32, // traction control cut
 }
 
 impl DrivenWheel {
@@ -172,6 +1432,7 @@ impl DrivenWheel {
             max_torque,
             max_speed,
             max_power,
+            throttle_scale: 1.,
         }
     }
 
@@ -184,6 +1445,16 @@ impl DrivenWheel {
     }
 }
 
+impl RollbackState for DrivenWheel {
+    type State = f32;
+    fn save(&self) -> Self::State {
+        self.throttle_scale
+    }
+    fn restore(&mut self, state: &Self::State) {
+        self.throttle_scale = *state;
+    }
+}
+
 pub fn driven_wheel_system(
     mut joints: Query<(&mut Joint, &DrivenWheel)>,
     control: Res<CarControl>,
@@ -191,7 +1462,9 @@ pub fn driven_wheel_system(
     for (mut joint, driven_wheel) in joints.iter_mut() {
         let power_limited_torque = (driven_wheel.max_power / joint.qd).abs();
         if joint.qd.abs() < driven_wheel.max_speed {
-            joint.tau += control.throttle * driven_wheel.max_torque.min(power_limited_torque);
+            joint.tau += driven_wheel.throttle_scale
+                * control.throttle
+                * driven_wheel.max_torque.min(power_limited_torque);
         }
     }
 }
@@ -199,11 +1472,19 @@ pub fn driven_wheel_system(
 #[derive(Component)]
 pub struct BrakeWheel {
     pub max_torque: f32,
+    pub regen_torque: f32, // part of the brake torque already taken by regenerative braking
+    pub modulation: f32,   // share of the pedal let through by the abs valves
+    pub assist_torque: f32, // extra brake torque asked for by stability control
 }
 
 impl BrakeWheel {
     pub fn new(max_torque: f32) -> Self {
-        Self { max_torque }
+        Self {
+            max_torque,
+            regen_torque: 0.,
+            modulation: 1.,
+            assist_torque: 0.,
+        }
     }
 
     pub fn from_def(brake_wheel_def: &BrakeWheelDef) -> Self {
@@ -211,8 +1492,22 @@ impl BrakeWheel {
     }
 }
 
+impl RollbackState for BrakeWheel {
+    type State = [f32; 2];
+    fn save(&self) -> Self::State {
+        [self.modulation, self.assist_torque]
+    }
+    fn restore(&mut self, state: &Self::State) {
+        [self.modulation, self.assist_torque] = *state;
+    }
+}
+
 pub fn brake_wheel_system(mut joints: Query<(&mut Joint, &BrakeWheel)>, control: Res<CarControl>) {
     for (mut joint, brake_wheel) in joints.iter_mut() {
-        joint.tau += -control.brake * brake_wheel.max_torque * joint.qd.min(1.).max(-1.);
+        let torque = (brake_wheel.modulation * control.brake * brake_wheel.max_torque
+            - brake_wheel.regen_torque)
+            .max(0.)
+            + brake_wheel.assist_torque;
+        joint.tau += -torque * joint.qd.clamp(-1., 1.);
     }
 }
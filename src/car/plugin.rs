@@ -1,19 +1,31 @@
 use crate::{
+    contact_solver::ContactSolver,
+    divergence::{DivergencePlugin, RollbackAppExt},
     joint::{bevy_joint_positions, Joint},
+    physics_diagnostics::PhysicsDiagnosticsPlugin,
+    simulation::{PostStepSchedule, PostStepSet, SimulationPlugin},
     structure::loop_1,
+    surface::SurfaceMap,
+    terrain::Terrain,
+    wind::WindPlugin,
+};
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
 };
-use bevy::prelude::*;
 use bevy_integrator::{
-    integrator::{initialize_state, integrator_schedule, PhysicsSchedule, Solver},
+    integrator::{initialize_state, PhysicsSchedule, Solver},
     recorder::{create_recorder, initialize_recorder, load_recorded_data, recorder_system},
 };
 
 use super::{
-    build, build_from_json,
+    assist, brakes, build, build_from_json,
     camera_az_el::{self, camera_builder},
     control::{self, CarControl},
     create_car_json::car_json,
+    electric,
     environment::build_environment,
+    physics, powertrain,
     schedule::{create_physics_schedule, set_replay_data},
 };
 
@@ -30,23 +42,70 @@ pub struct CarPlugin {
     pub time_step: f32,
     pub camera: bool,
     pub environment: bool,
+    pub diagnostics: bool,
+    pub contact_solver: bool, // velocity level contacts for the colliders instead of penalty springs
+    pub full_car: bool, // rack, disc brakes, assists, aero, warm tires and crates on the demo car
+}
+
+impl Default for CarPlugin {
+    fn default() -> Self {
+        Self {
+            mode: Mode::None,
+            time_step: 0.002, // 0.002 -> 500 fps
+            camera: true,
+            environment: true,
+            diagnostics: false,
+            contact_solver: false,
+            full_car: false,
+        }
+    }
 }
 
 impl CarPlugin {
     pub fn setup_physics_simulation(&self, app: &mut App) {
         // run the physics simulation with user control
         let schedule = create_physics_schedule();
+        if self.contact_solver {
+            app.init_resource::<ContactSolver>();
+        }
         app.add_schedule(PhysicsSchedule, schedule) // add the physics schedule
             .insert_resource(Solver::RK4) // set the solver to use
             .insert_resource(FixedTime::new_from_secs(self.time_step)) // set the fixed timestep
-            .add_system(integrator_schedule::<Joint>.in_schedule(CoreSchedule::FixedUpdate)) // run the physics schedule in the fixed timestep loop
+            .add_plugin(SimulationPlugin) // step the physics schedule (pause, single step, time scaling)
+            .add_plugin(DivergencePlugin) // halt or roll back on non-finite or runaway states
+            .add_plugin(WindPlugin) // still air unless a wind was inserted or loaded with the model
+            .add_systems(
+                (
+                    physics::tire_condition_system,
+                    powertrain::powertrain_shift_system,
+                    electric::battery_system,
+                    brakes::brake_update_system,
+                    // both set Brake state, the assist modulation goes in first
+                    assist::driver_assist_system.before(brakes::brake_update_system),
+                )
+                    .in_set(PostStepSet::Update)
+                    .in_schedule(PostStepSchedule),
+            ) // states updated once per step
+            .add_rollback_component::<physics::TireCondition>()
+            .add_rollback_component::<physics::DrivenWheel>()
+            .add_rollback_component::<physics::BrakeWheel>()
+            .add_rollback_component::<brakes::BrakeHydraulics>()
+            .add_rollback_component::<brakes::Brake>()
+            .add_rollback_component::<powertrain::Powertrain>()
+            .add_rollback_component::<electric::ElectricDrive>()
+            .add_rollback_component::<assist::DriverAssist>()
             .add_system(control::user_control_system) // control the car with a gamepad
+            .add_system(control::simulation_control_system) // pause, step and time scale from the keyboard
             .init_resource::<CarControl>();
     }
 }
 
 impl Plugin for CarPlugin {
     fn build(&self, app: &mut App) {
+        // flat ground with uniform friction, unless a terrain or surface map was inserted before
+        // the plugin was added
+        app.init_resource::<Terrain>().init_resource::<SurfaceMap>();
+
         match self.mode {
             Mode::Record => {
                 app.add_startup_system(create_recorder)
@@ -88,14 +147,25 @@ impl Plugin for CarPlugin {
             app.add_startup_system(environment_setup_system);
         }
 
+        // log physics timing next to the frame time, to see where a slow frame comes from
+        if self.diagnostics {
+            app.add_plugin(PhysicsDiagnosticsPlugin)
+                .add_plugin(FrameTimeDiagnosticsPlugin)
+                .add_plugin(LogDiagnosticsPlugin::default());
+        }
+
         // car setup
-        app.add_startup_system(setup_system) // setup the car model and environment
-            .add_startup_systems(
-                (initialize_state::<Joint>,)
-                    .chain()
-                    .in_base_set(StartupSet::PostStartup),
-            )
-            .add_system(bevy_joint_positions);
+        if self.full_car {
+            app.add_startup_system(setup_full_system);
+        } else {
+            app.add_startup_system(setup_system); // setup the car model and environment
+        }
+        app.add_startup_systems(
+            (initialize_state::<Joint>,)
+                .chain()
+                .in_base_set(StartupSet::PostStartup),
+        )
+        .add_system(bevy_joint_positions);
     }
 }
 
@@ -112,10 +182,19 @@ pub fn setup_system(
     }
 }
 
+pub fn setup_full_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    build::build_full_model(&mut commands, &mut meshes, &mut materials);
+}
+
 pub fn environment_setup_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain: Res<Terrain>,
 ) {
-    build_environment(&mut commands, &mut meshes, &mut materials);
+    build_environment(&mut commands, &mut meshes, &mut materials, &terrain);
 }
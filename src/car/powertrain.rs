@@ -0,0 +1,314 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{
+    divergence::RollbackState,
+    joint::Joint,
+    lookup::LookupTable,
+    serialize::{PowertrainDef, ShiftModeDef},
+};
+
+use super::{
+    control::CarControl,
+    differential::{Differential, DriveOutput},
+};
+
+const RPM_TO_RAD_S: f32 = 2. * PI / 60.;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ShiftMode {
+    Manual,
+    Automatic {
+        upshift_rpm: f32,
+        downshift_rpm: f32,
+    },
+}
+
+impl ShiftMode {
+    pub fn from_def(shift_mode_def: &ShiftModeDef) -> Self {
+        match shift_mode_def {
+            ShiftModeDef::Manual => ShiftMode::Manual,
+            ShiftModeDef::Automatic {
+                upshift_rpm,
+                downshift_rpm,
+            } => ShiftMode::Automatic {
+                upshift_rpm: *upshift_rpm,
+                downshift_rpm: *downshift_rpm,
+            },
+        }
+    }
+}
+
+// engine, clutch, gearbox and final drive. The engine is its own joint (a flywheel on the
+// chassis), so its speed is integrated with the rest of the model and its torque reacts on the
+// chassis. The drive torque goes through a differential, or without one is split equally
+// between the driven wheels.
+// Gear: -1 reverse, 0 neutral, 1.. forward gears. In manual mode the gear follows
+// CarControl::gear and CarControl::clutch is the pedal; in automatic mode CarControl::gear
+// selects drive (> 0), neutral or reverse and the clutch engages on its own when pulling away.
+#[derive(Component)]
+pub struct Powertrain {
+    pub engine: Entity,
+    pub wheels: Vec<Entity>,
+    pub differential: Option<Entity>,
+    torque_curve: LookupTable, // full load torque (Nm) against rpm
+    idle_rpm: f32,
+    max_rpm: f32,
+    friction: [f32; 2], // engine drag, Nm and Nm per rad/s
+    clutch_capacity: f32,
+    clutch_damping: f32, // Nm per rad/s of slip, until the capacity is reached
+    gear_ratios: Vec<f32>,
+    reverse_ratio: f32,
+    final_drive: f32,
+    shift_mode: ShiftMode,
+    shift_time: f32,
+    pub gear: i32,
+    pub rpm: f32,
+    pub engine_torque: f32,
+    pub clutch_torque: f32,
+    pub shift_timer: f32, // time left in the current shift, the clutch is open meanwhile
+    pub throttle_scale: f32, // traction control cut
+}
+
+impl Powertrain {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        engine: Entity,
+        wheels: Vec<Entity>,
+        torque_curve: LookupTable,
+        idle_rpm: f32,
+        max_rpm: f32,
+        friction: [f32; 2],
+        clutch_capacity: f32,
+        clutch_damping: f32,
+        gear_ratios: Vec<f32>,
+        reverse_ratio: f32,
+        final_drive: f32,
+        shift_mode: ShiftMode,
+        shift_time: f32,
+    ) -> Self {
+        Self {
+            engine,
+            wheels,
+            differential: None,
+            torque_curve,
+            idle_rpm,
+            max_rpm,
+            friction,
+            clutch_capacity,
+            clutch_damping,
+            gear_ratios,
+            reverse_ratio,
+            final_drive,
+            shift_mode,
+            shift_time,
+            gear: 0,
+            rpm: idle_rpm,
+            engine_torque: 0.,
+            clutch_torque: 0.,
+            shift_timer: 0.,
+            throttle_scale: 1.,
+        }
+    }
+
+    pub fn with_differential(mut self, differential: Entity) -> Self {
+        self.differential = Some(differential);
+        self
+    }
+
+    pub fn from_def(
+        powertrain_def: &PowertrainDef,
+        engine: Entity,
+        wheels: Vec<Entity>,
+    ) -> Result<Self, String> {
+        if powertrain_def.gear_ratios.is_empty() {
+            return Err("no forward gear ratios".to_string());
+        }
+        if powertrain_def.final_drive <= 0. {
+            return Err(format!(
+                "final drive must be positive, got {}",
+                powertrain_def.final_drive
+            ));
+        }
+        Ok(Self::new(
+            engine,
+            wheels,
+            LookupTable::curve(&powertrain_def.torque_curve)
+                .map_err(|e| format!("torque curve: {}", e))?,
+            powertrain_def.idle_rpm,
+            powertrain_def.max_rpm,
+            powertrain_def.friction,
+            powertrain_def.clutch_capacity,
+            powertrain_def.clutch_damping,
+            powertrain_def.gear_ratios.clone(),
+            powertrain_def.reverse_ratio,
+            powertrain_def.final_drive,
+            ShiftMode::from_def(&powertrain_def.shift_mode),
+            powertrain_def.shift_time,
+        ))
+    }
+
+    // engine speed / wheel speed in a gear
+    pub fn ratio(&self, gear: i32) -> f32 {
+        let gearbox = match gear {
+            0 => 0.,
+            gear if gear < 0 => -self.reverse_ratio,
+            gear => {
+                let index = (gear as usize - 1).min(self.gear_ratios.len().saturating_sub(1));
+                self.gear_ratios.get(index).copied().unwrap_or(0.)
+            }
+        };
+        gearbox * self.final_drive
+    }
+
+    pub fn max_gear(&self) -> i32 {
+        self.gear_ratios.len() as i32
+    }
+
+    // engine torque at a speed (rad/s), with the idle governor and rev limiter
+    fn engine_torque(&self, speed: f32, throttle: f32) -> f32 {
+        let rpm = speed / RPM_TO_RAD_S;
+        let full_load = self.torque_curve.interpolate_1d(rpm);
+        let idle_speed = self.idle_rpm * RPM_TO_RAD_S;
+
+        // the idle governor opens the throttle just enough to hold idle
+        let idle_throttle = ((idle_speed - speed) / (0.1 * idle_speed)).clamp(0., 1.);
+        let throttle = if rpm < self.max_rpm {
+            throttle.max(idle_throttle)
+        } else {
+            0.
+        };
+        throttle * full_load - self.friction[0] * speed.signum().max(0.) - self.friction[1] * speed
+    }
+
+    // clutch engagement, 0 open to 1 closed
+    fn clutch_engagement(&self, control: &CarControl) -> f32 {
+        if self.gear == 0 || self.shift_timer > 0. {
+            return 0.;
+        }
+        match self.shift_mode {
+            ShiftMode::Manual => 1. - control.clutch,
+            // pull away between idle and 1.5 x idle
+            ShiftMode::Automatic { .. } => {
+                ((self.rpm - self.idle_rpm) / (0.5 * self.idle_rpm)).clamp(0., 1.)
+            }
+        }
+    }
+}
+
+impl RollbackState for Powertrain {
+    type State = (i32, f32, f32);
+    fn save(&self) -> Self::State {
+        (self.gear, self.shift_timer, self.throttle_scale)
+    }
+    fn restore(&mut self, state: &Self::State) {
+        (self.gear, self.shift_timer, self.throttle_scale) = *state;
+    }
+}
+
+// engine and clutch torques, in the force systems
+pub fn powertrain_system(
+    mut powertrains: Query<&mut Powertrain>,
+    mut differentials: Query<&mut Differential>,
+    mut joints: Query<&mut Joint>,
+    control: Res<CarControl>,
+) {
+    for mut powertrain in powertrains.iter_mut() {
+        let Ok(engine) = joints.get(powertrain.engine) else {
+            continue;
+        };
+        let engine_speed = engine.qd;
+
+        // gearbox input speed from the differential, or the mean wheel speed
+        let ratio = powertrain.ratio(powertrain.gear);
+        let wheel_speed = match powertrain.differential {
+            Some(differential) => {
+                DriveOutput::Differential(differential).speed(&differentials, &joints)
+            }
+            None => {
+                let wheel_speeds: Vec<f32> = powertrain
+                    .wheels
+                    .iter()
+                    .filter_map(|wheel| joints.get(*wheel).ok().map(|joint| joint.qd))
+                    .collect();
+                wheel_speeds.iter().sum::<f32>() / wheel_speeds.len().max(1) as f32
+            }
+        };
+        let input_speed = wheel_speed * ratio;
+
+        let engine_torque =
+            powertrain.engine_torque(engine_speed, powertrain.throttle_scale * control.throttle);
+        let capacity = powertrain.clutch_engagement(&control) * powertrain.clutch_capacity;
+        let clutch_torque =
+            (powertrain.clutch_damping * (engine_speed - input_speed)).clamp(-capacity, capacity);
+
+        powertrain.rpm = engine_speed / RPM_TO_RAD_S;
+        powertrain.engine_torque = engine_torque;
+        powertrain.clutch_torque = clutch_torque;
+
+        if let Ok(mut engine) = joints.get_mut(powertrain.engine) {
+            engine.tau += engine_torque - clutch_torque;
+        }
+        if let Some(differential) = powertrain.differential {
+            Differential::drive(
+                differential,
+                clutch_torque * ratio,
+                &mut differentials,
+                &mut joints,
+            );
+        } else {
+            let wheel_torque = clutch_torque * ratio / powertrain.wheels.len().max(1) as f32;
+            for wheel in powertrain.wheels.iter() {
+                if let Ok(mut joint) = joints.get_mut(*wheel) {
+                    joint.tau += wheel_torque;
+                }
+            }
+        }
+    }
+}
+
+// gear changes, once per step
+pub fn powertrain_shift_system(
+    mut powertrains: Query<&mut Powertrain>,
+    control: Res<CarControl>,
+    fixed_time: Res<FixedTime>,
+) {
+    let dt = fixed_time.period.as_secs_f32();
+    for mut powertrain in powertrains.iter_mut() {
+        powertrain.shift_timer = (powertrain.shift_timer - dt).max(0.);
+        if powertrain.shift_timer > 0. {
+            continue;
+        }
+
+        let max_gear = powertrain.max_gear();
+        let target = match powertrain.shift_mode {
+            ShiftMode::Manual => control.gear.clamp(-1, max_gear),
+            ShiftMode::Automatic {
+                upshift_rpm,
+                downshift_rpm,
+            } => match control.gear {
+                gear if gear > 0 => {
+                    let current = powertrain.gear.max(1);
+                    if powertrain.gear < 1 {
+                        1
+                    } else if powertrain.rpm > upshift_rpm && current < max_gear {
+                        current + 1
+                    } else if powertrain.rpm < downshift_rpm && current > 1 {
+                        current - 1
+                    } else {
+                        current
+                    }
+                }
+                0 => 0,
+                _ => -1,
+            }
+            .clamp(-1, max_gear),
+        };
+
+        if target != powertrain.gear {
+            powertrain.gear = target;
+            powertrain.shift_timer = powertrain.shift_time;
+        }
+    }
+}
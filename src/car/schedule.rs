@@ -1,11 +1,25 @@
-use super::physics::{
-    brake_wheel_system, driven_wheel_system, steering_system, suspension_system,
-    tire_contact_system,
+use super::{
+    aero::aero_system,
+    brakes::brake_system,
+    electric::electric_drive_system,
+    pacejka::pacejka_tire_system,
+    physics::{
+        anti_roll_bar_system, brake_wheel_system, driven_wheel_system, steering_rack_system,
+        steering_system, suspension_kinematics_system, suspension_system, table_tire_system,
+        tire_contact_system,
+    },
+    powertrain::powertrain_system,
 };
 use bevy::prelude::*;
 
 use crate::{
+    collision::collision_system,
+    contact_solver::contact_solver_system,
+    divergence::{check_forces, check_loop_1, check_loop_23, not_diverged},
     joint::Joint,
+    physics_diagnostics::{
+        end_forces_timer, end_loop_1_timer, end_loop_23_timer, start_stage_timer,
+    },
     structure::{apply_external_forces, loop_1, loop_23},
 };
 use bevy_integrator::{
@@ -17,14 +31,43 @@ use bevy_integrator::{
 pub fn create_physics_schedule() -> Schedule {
     let mut physics_schedule = Schedule::new();
     physics_schedule.add_physics_systems::<Joint, _, _, _>(
-        (steering_system, loop_1).chain(),
+        (
+            start_stage_timer,
+            steering_system,
+            steering_rack_system,
+            suspension_kinematics_system,
+            loop_1,
+            check_loop_1,
+            end_loop_1_timer,
+        )
+            .chain(),
         (
             suspension_system,
+            anti_roll_bar_system,
             tire_contact_system,
+            pacejka_tire_system,
+            table_tire_system,
             driven_wheel_system,
+            powertrain_system,
+            electric_drive_system
+                .before(brake_wheel_system)
+                .before(brake_system), // regen before friction braking
             brake_wheel_system,
-        ),
-        (apply_external_forces, loop_23).chain(),
+            brake_system,
+            aero_system,
+            collision_system,
+        )
+            .distributive_run_if(not_diverged),
+        (
+            end_forces_timer,
+            check_forces,
+            apply_external_forces,
+            loop_23,
+            contact_solver_system,
+            check_loop_23,
+            end_loop_23_timer,
+        )
+            .chain(),
     );
 
     physics_schedule
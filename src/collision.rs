@@ -0,0 +1,408 @@
+use bevy::prelude::*;
+
+use crate::{
+    contact_solver::ContactSolver,
+    joint::Joint,
+    mesh::Mesh,
+    serialize::ColliderDef,
+    surface::SurfaceMap,
+    sva::{Force, Vector, Xform},
+    terrain::Terrain,
+};
+
+// below this sliding speed friction is viscous instead of coulomb (keeps the force continuous)
+const FRICTION_VELOCITY: f32 = 0.05;
+
+// number of points around the rim of a cylinder used for contact
+const CYLINDER_RIM_POINTS: usize = 12;
+
+// number of spheres along the axis of a capsule used for contact
+const CAPSULE_POINTS: usize = 5;
+
+pub struct CollisionShape {
+    pub mesh: Mesh,
+    pub xform: Xform, // shape frame relative to the joint frame
+}
+
+impl CollisionShape {
+    pub fn new(mesh: Mesh, xform: Xform) -> Self {
+        Self { mesh, xform }
+    }
+
+    // points that are tested against other geometry (shape coordinates), each with a radius
+    pub fn sample_points(&self) -> Vec<(Vector, f32)> {
+        match &self.mesh {
+            Mesh::Box(b) => {
+                let mut corners = Vec::with_capacity(8);
+                for x in [b.min_x, b.max_x] {
+                    for y in [b.min_y, b.max_y] {
+                        for z in [b.min_z, b.max_z] {
+                            corners.push((Vector::new(x, y, z), 0.));
+                        }
+                    }
+                }
+                corners
+            }
+            Mesh::Cylinder(c) => {
+                let mut rim = Vec::with_capacity(2 * CYLINDER_RIM_POINTS);
+                for y in [-c.height / 2., c.height / 2.] {
+                    for i in 0..CYLINDER_RIM_POINTS {
+                        let angle =
+                            2. * std::f32::consts::PI * i as f32 / CYLINDER_RIM_POINTS as f32;
+                        rim.push((
+                            Vector::new(c.radius * angle.cos(), y, c.radius * angle.sin()),
+                            0.,
+                        ));
+                    }
+                }
+                rim
+            }
+            Mesh::Sphere(s) => vec![(Vector::zeros(), s.radius)],
+            Mesh::Capsule(c) => (0..CAPSULE_POINTS)
+                .map(|i| {
+                    let y = c.length * (i as f32 / (CAPSULE_POINTS - 1) as f32 - 0.5);
+                    (Vector::new(0., y, 0.), c.radius)
+                })
+                .collect(),
+        }
+    }
+
+    // signed distance from a point (shape coordinates) to the surface, and the outward normal
+    pub fn signed_distance(&self, p: Vector) -> (f32, Vector) {
+        match &self.mesh {
+            Mesh::Box(b) => {
+                let center = Vector::new(
+                    (b.min_x + b.max_x) / 2.,
+                    (b.min_y + b.max_y) / 2.,
+                    (b.min_z + b.max_z) / 2.,
+                );
+                let half = Vector::new(
+                    (b.max_x - b.min_x) / 2.,
+                    (b.max_y - b.min_y) / 2.,
+                    (b.max_z - b.min_z) / 2.,
+                );
+                let p = p - center;
+                let q = p.abs() - half;
+                let sign = p.map(|x| if x < 0. { -1. } else { 1. });
+                let outside = q.map(|x| x.max(0.));
+                let outside_distance = outside.norm();
+                if outside_distance > 0. {
+                    (
+                        outside_distance,
+                        sign.component_mul(&outside) / outside_distance,
+                    )
+                } else {
+                    // inside, push out through the nearest face
+                    let axis = q.imax();
+                    let mut normal = Vector::zeros();
+                    normal[axis] = sign[axis];
+                    (q[axis], normal)
+                }
+            }
+            Mesh::Cylinder(c) => {
+                let radial = (p.x * p.x + p.z * p.z).sqrt();
+                let radial_dir = if radial > 1.0e-6 {
+                    Vector::new(p.x / radial, 0., p.z / radial)
+                } else {
+                    Vector::new(1., 0., 0.)
+                };
+                let axial_dir = Vector::new(0., if p.y < 0. { -1. } else { 1. }, 0.);
+                let dr = radial - c.radius;
+                let dy = p.y.abs() - c.height / 2.;
+                if dr > 0. && dy > 0. {
+                    // outside, nearest to the rim
+                    let distance = (dr * dr + dy * dy).sqrt();
+                    (distance, (dr * radial_dir + dy * axial_dir) / distance)
+                } else if dr > dy {
+                    (dr, radial_dir)
+                } else {
+                    (dy, axial_dir)
+                }
+            }
+            Mesh::Sphere(s) => {
+                let distance = p.norm();
+                (distance - s.radius, direction_or_x(p, distance))
+            }
+            Mesh::Capsule(c) => {
+                let closest = Vector::new(0., p.y.clamp(-c.length / 2., c.length / 2.), 0.);
+                let d = p - closest;
+                let distance = d.norm();
+                (distance - c.radius, direction_or_x(d, distance))
+            }
+        }
+    }
+
+    // spheres and capsules are fully described by their sample points
+    fn is_rounded(&self) -> bool {
+        matches!(self.mesh, Mesh::Sphere(_) | Mesh::Capsule(_))
+    }
+}
+
+fn direction_or_x(v: Vector, length: f32) -> Vector {
+    if length > 1.0e-6 {
+        v / length
+    } else {
+        Vector::new(1., 0., 0.)
+    }
+}
+
+#[derive(Component)]
+pub struct Collider {
+    pub shapes: Vec<CollisionShape>,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub friction: f32,
+    pub group: u32, // colliders in the same (non zero) group do not collide with each other
+}
+
+impl Collider {
+    pub fn new(
+        shapes: Vec<CollisionShape>,
+        stiffness: f32,
+        damping: f32,
+        friction: f32,
+        group: u32,
+    ) -> Self {
+        Self {
+            shapes,
+            stiffness,
+            damping,
+            friction,
+            group,
+        }
+    }
+
+    pub fn from_def(collider_def: &ColliderDef) -> Self {
+        let shapes = collider_def
+            .shapes
+            .iter()
+            .map(|shape_def| {
+                CollisionShape::new(
+                    Mesh::from_mesh_type_def(&shape_def.shape),
+                    Xform::from_def(&shape_def.transform),
+                )
+            })
+            .collect();
+        Self::new(
+            shapes,
+            collider_def.stiffness,
+            collider_def.damping,
+            collider_def.friction,
+            collider_def.group,
+        )
+    }
+
+    fn collides_with(&self, other: &Collider) -> bool {
+        self.group == 0 || self.group != other.group
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Contact {
+    pub body: Entity,
+    pub other: Option<Entity>, // None for the ground
+    pub point: Vector,         // absolute coordinates
+    pub normal: Vector,        // absolute coordinates, the direction the contact pushes body
+    pub depth: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub friction: f32,
+}
+
+// velocity of a point (absolute coordinates) fixed to a joint
+pub fn point_velocity(joint: &Joint, point: Vector) -> Vector {
+    (joint.x.inverse() * joint.v).velocity_point(point).vel
+}
+
+// shape coordinates to absolute coordinates
+fn shape_to_abs(joint: &Joint, shape: &CollisionShape) -> Xform {
+    (shape.xform * joint.x).inverse()
+}
+
+fn ground_contacts(
+    entity: Entity,
+    joint: &Joint,
+    collider: &Collider,
+    terrain: &Terrain,
+    surfaces: &SurfaceMap,
+    contacts: &mut Vec<Contact>,
+) {
+    for shape in collider.shapes.iter() {
+        let x_abs = shape_to_abs(joint, shape);
+        for (point, radius) in shape.sample_points() {
+            let point_abs = x_abs.transform_point(point);
+            // the ground is treated as the plane through the point below the sample
+            let surface = terrain.surface_at(point_abs);
+            let distance = (point_abs - surface.point).dot(&surface.normal);
+            let depth = radius - distance;
+            if depth > 0. {
+                let material = surfaces.material_at(&surface);
+                contacts.push(Contact {
+                    body: entity,
+                    other: None,
+                    point: point_abs - distance * surface.normal,
+                    normal: surface.normal,
+                    depth,
+                    stiffness: collider.stiffness,
+                    damping: collider.damping,
+                    friction: collider.friction * material.friction_scale,
+                });
+            }
+        }
+    }
+}
+
+// sample points of shape a tested against the surface of shape b
+#[allow(clippy::too_many_arguments)]
+fn shape_contacts(
+    entity_a: Entity,
+    joint_a: &Joint,
+    shape_a: &CollisionShape,
+    entity_b: Entity,
+    joint_b: &Joint,
+    shape_b: &CollisionShape,
+    template: &Contact,
+    contacts: &mut Vec<Contact>,
+) {
+    let a_to_abs = shape_to_abs(joint_a, shape_a);
+    let abs_to_b = shape_b.xform * joint_b.x;
+    let b_to_abs = abs_to_b.inverse();
+    for (point, radius) in shape_a.sample_points() {
+        let point_abs = a_to_abs.transform_point(point);
+        let point_b = abs_to_b.transform_point(point_abs);
+        let (distance, normal_b) = shape_b.signed_distance(point_b);
+        if distance < radius {
+            contacts.push(Contact {
+                body: entity_a,
+                other: Some(entity_b),
+                point: b_to_abs.transform_point(point_b - distance * normal_b),
+                normal: b_to_abs * normal_b,
+                depth: radius - distance,
+                ..template.clone()
+            });
+        }
+    }
+}
+
+fn bounding_sphere(joint: &Joint, shape: &CollisionShape) -> (Vector, f32) {
+    let radius = shape
+        .sample_points()
+        .iter()
+        .map(|(point, radius)| point.norm() + radius)
+        .fold(0., f32::max);
+    (
+        shape_to_abs(joint, shape).transform_point(Vector::zeros()),
+        radius,
+    )
+}
+
+fn pair_contacts(
+    (entity_a, joint_a, collider_a): (Entity, &Joint, &Collider),
+    (entity_b, joint_b, collider_b): (Entity, &Joint, &Collider),
+    contacts: &mut Vec<Contact>,
+) {
+    // springs in series, averaged damping, geometric mean friction
+    let template = Contact {
+        body: entity_a,
+        other: Some(entity_b),
+        point: Vector::zeros(),
+        normal: Vector::zeros(),
+        depth: 0.,
+        stiffness: collider_a.stiffness * collider_b.stiffness
+            / (collider_a.stiffness + collider_b.stiffness),
+        damping: 0.5 * (collider_a.damping + collider_b.damping),
+        friction: (collider_a.friction * collider_b.friction).sqrt(),
+    };
+
+    for shape_a in collider_a.shapes.iter() {
+        let (center_a, radius_a) = bounding_sphere(joint_a, shape_a);
+        for shape_b in collider_b.shapes.iter() {
+            let (center_b, radius_b) = bounding_sphere(joint_b, shape_b);
+            if (center_a - center_b).norm() > radius_a + radius_b {
+                continue;
+            }
+
+            shape_contacts(
+                entity_a, joint_a, shape_a, entity_b, joint_b, shape_b, &template, contacts,
+            );
+            // two rounded shapes are fully handled in one direction, anything else needs both
+            if !(shape_a.is_rounded() && shape_b.is_rounded()) {
+                shape_contacts(
+                    entity_b, joint_b, shape_b, entity_a, joint_a, shape_a, &template, contacts,
+                );
+            }
+        }
+    }
+}
+
+pub fn find_contacts(
+    bodies: &[(Entity, &Joint, &Collider)],
+    terrain: &Terrain,
+    surfaces: &SurfaceMap,
+) -> Vec<Contact> {
+    let mut contacts = Vec::new();
+    for (ind, &(entity, joint, collider)) in bodies.iter().enumerate() {
+        ground_contacts(entity, joint, collider, terrain, surfaces, &mut contacts);
+        for &other in bodies[ind + 1..].iter() {
+            if collider.collides_with(other.2) {
+                pair_contacts((entity, joint, collider), other, &mut contacts);
+            }
+        }
+    }
+    contacts
+}
+
+// penalty (spring damper) contact forces, with regularized coulomb friction
+pub fn collision_system(
+    mut joints: Query<&mut Joint>,
+    colliders: Query<(Entity, &Collider)>,
+    solver: Option<Res<ContactSolver>>,
+    terrain: Res<Terrain>,
+    surfaces: Res<SurfaceMap>,
+) {
+    // contacts are handled after loop_23 instead
+    if solver.is_some() {
+        return;
+    }
+
+    let contacts = {
+        let bodies: Vec<(Entity, &Joint, &Collider)> = colliders
+            .iter()
+            .filter_map(|(entity, collider)| {
+                joints
+                    .get(entity)
+                    .ok()
+                    .map(|joint| (entity, joint, collider))
+            })
+            .collect();
+        find_contacts(&bodies, &terrain, &surfaces)
+    };
+
+    for contact in contacts.iter() {
+        let Ok(body) = joints.get(contact.body) else {
+            continue;
+        };
+        let mut velocity = point_velocity(body, contact.point);
+        if let Some(other) = contact.other.and_then(|other| joints.get(other).ok()) {
+            velocity -= point_velocity(other, contact.point);
+        }
+
+        let normal_vel = velocity.dot(&contact.normal);
+        let normal_force =
+            (contact.stiffness * contact.depth - contact.damping * normal_vel).max(0.);
+        let tangent_vel = velocity - normal_vel * contact.normal;
+        let friction_force = -contact.friction * normal_force * tangent_vel
+            / tangent_vel.norm().max(FRICTION_VELOCITY);
+        let force = Force::force_point(
+            normal_force * contact.normal + friction_force,
+            contact.point,
+        );
+
+        if let Ok(mut joint) = joints.get_mut(contact.body) {
+            joint.f_ext += force;
+        }
+        if let Some(mut joint) = contact.other.and_then(|other| joints.get_mut(other).ok()) {
+            joint.f_ext -= force;
+        }
+    }
+}
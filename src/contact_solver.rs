@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    collision::{find_contacts, Collider},
+    joint::{Base, Joint},
+    surface::SurfaceMap,
+    sva::{Force, Motion, Vector, Xform},
+    terrain::Terrain,
+};
+
+// velocity level contact solver with coulomb friction cones, solved by projected gauss-seidel.
+// Insert the resource to use it instead of the penalty contacts in collision_system.
+#[derive(Resource)]
+pub struct ContactSolver {
+    pub iterations: usize,
+    pub baumgarte: f32, // fraction of the penetration removed per time step
+    pub slop: f32,      // penetration that is allowed without correction
+}
+
+impl Default for ContactSolver {
+    fn default() -> Self {
+        Self {
+            iterations: 20,
+            baumgarte: 0.2,
+            slop: 0.001,
+        }
+    }
+}
+
+// copy of the articulated body quantities needed to compute the response to test forces
+struct Node {
+    entity: Entity,
+    parent: Option<usize>,
+    root: usize,
+    x: Xform,
+    xl: Xform,
+    s: Motion,
+    uu: Force,
+    dd: f32,
+    v: Motion,
+    a: Motion,
+}
+
+// one direction of one contact
+struct Row {
+    body: usize,
+    other: Option<usize>,
+    point: Vector,
+    direction: Vector,
+}
+
+impl Row {
+    // the test force for a unit multiplier, per node
+    fn forces(&self) -> Vec<(usize, Force)> {
+        let force = Force::force_point(self.direction, self.point);
+        let mut forces = vec![(self.body, force)];
+        if let Some(other) = self.other {
+            forces.push((other, -1. * force));
+        }
+        forces
+    }
+
+    // relative velocity along the row direction, given a spatial velocity for each node
+    fn velocity(&self, nodes: &[Node], motion: &[Motion]) -> f32 {
+        let point_velocity = |ind: usize| {
+            (nodes[ind].x.inverse() * motion[ind])
+                .velocity_point(self.point)
+                .vel
+        };
+        let mut velocity = point_velocity(self.body);
+        if let Some(other) = self.other {
+            velocity -= point_velocity(other);
+        }
+        velocity.dot(&self.direction)
+    }
+}
+
+fn collect_nodes(
+    entity: Entity,
+    parent: Option<usize>,
+    root: usize,
+    joint_children_query: &Query<&Children, With<Joint>>,
+    joint_query: &Query<&mut Joint>,
+    nodes: &mut Vec<Node>,
+) {
+    let Ok(joint) = joint_query.get(entity) else {
+        return;
+    };
+    let ind = nodes.len();
+    nodes.push(Node {
+        entity,
+        parent,
+        root,
+        x: joint.x,
+        xl: joint.xl,
+        s: joint.s,
+        uu: joint.uu,
+        dd: joint.dd,
+        v: joint.v,
+        a: joint.a,
+    });
+
+    if let Ok(children) = joint_children_query.get(entity) {
+        for child in children.iter() {
+            collect_nodes(
+                *child,
+                Some(ind),
+                root,
+                joint_children_query,
+                joint_query,
+                nodes,
+            );
+        }
+    }
+}
+
+// change in joint acceleration (and spatial acceleration) due to external test forces, with
+// zero velocity and joint torque. Reuses the articulated inertia from the last loop_23.
+fn test_force_response(nodes: &[Node], forces: &[(usize, Force)]) -> (Vec<f32>, Vec<Motion>) {
+    let mut paa = vec![Force::zero(); nodes.len()];
+    for (ind, force) in forces.iter() {
+        paa[*ind] -= nodes[*ind].x * *force;
+    }
+
+    // inward pass, children are always after their parents
+    let mut u = vec![0.; nodes.len()];
+    for ind in (0..nodes.len()).rev() {
+        let node = &nodes[ind];
+        if let Some(parent) = node.parent {
+            u[ind] = -(node.s.w.dot(&paa[ind].m) + node.s.v.dot(&paa[ind].f));
+            let pa = paa[ind] + ((u[ind] / node.dd) * node.uu);
+            paa[parent] += node.xl.inverse() * pa;
+        }
+    }
+
+    // outward pass
+    let mut qdd = vec![0.; nodes.len()];
+    let mut a = vec![Motion::zero(); nodes.len()];
+    for ind in 0..nodes.len() {
+        let node = &nodes[ind];
+        if let Some(parent) = node.parent {
+            let ap = node.xl * a[parent];
+            qdd[ind] = (u[ind] - (node.uu.m.dot(&ap.w) + node.uu.f.dot(&ap.v))) / node.dd;
+            a[ind] = ap + (qdd[ind] * node.s);
+        }
+    }
+    (qdd, a)
+}
+
+fn tangent_basis(normal: Vector) -> (Vector, Vector) {
+    let reference = if normal.x.abs() < 0.9 {
+        Vector::new(1., 0., 0.)
+    } else {
+        Vector::new(0., 1., 0.)
+    };
+    let t1 = normal.cross(&reference).normalize();
+    let t2 = normal.cross(&t1);
+    (t1, t2)
+}
+
+// runs after loop_23, and corrects the accelerations with the contact impulses
+#[allow(clippy::too_many_arguments)]
+pub fn contact_solver_system(
+    solver: Option<Res<ContactSolver>>,
+    fixed_time: Res<FixedTime>,
+    base_query: Query<Entity, With<Base>>,
+    joint_children_query: Query<&Children, With<Joint>>,
+    mut joint_query: Query<&mut Joint>,
+    collider_query: Query<(Entity, &Collider)>,
+    terrain: Res<Terrain>,
+    surfaces: Res<SurfaceMap>,
+) {
+    let Some(solver) = solver else { return };
+    let dt = fixed_time.period.as_secs_f32();
+
+    // contacts
+    let contacts = {
+        let bodies: Vec<(Entity, &Joint, &Collider)> = collider_query
+            .iter()
+            .filter_map(|(entity, collider)| {
+                joint_query
+                    .get(entity)
+                    .ok()
+                    .map(|joint| (entity, joint, collider))
+            })
+            .collect();
+        find_contacts(&bodies, &terrain, &surfaces)
+    };
+    if contacts.is_empty() {
+        return;
+    }
+
+    // flatten the tree(s), parents before children
+    let mut nodes = Vec::new();
+    for base_entity in base_query.iter() {
+        let root = nodes.len();
+        collect_nodes(
+            base_entity,
+            None,
+            root,
+            &joint_children_query,
+            &joint_query,
+            &mut nodes,
+        );
+    }
+    let index: HashMap<Entity, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(ind, node)| (node.entity, ind))
+        .collect();
+
+    // three rows per contact: normal, then two tangents
+    let mut rows = Vec::new();
+    let mut friction = Vec::new();
+    let mut target = Vec::new(); // normal velocity needed to push out of the penetration
+    for contact in contacts.iter() {
+        let Some(&body) = index.get(&contact.body) else {
+            continue;
+        };
+        let other = contact.other.and_then(|other| index.get(&other).copied());
+        let (t1, t2) = tangent_basis(contact.normal);
+        for direction in [contact.normal, t1, t2] {
+            rows.push(Row {
+                body,
+                other,
+                point: contact.point,
+                direction,
+            });
+        }
+        friction.push(contact.friction);
+        target.push(solver.baumgarte * (contact.depth - solver.slop).max(0.) / dt);
+    }
+    let n_rows = rows.len();
+
+    // velocity at the end of the step without contact impulses. The base acceleration is
+    // gravity (fictitious), so remove it to get the true acceleration.
+    let free_velocity: Vec<Motion> = nodes
+        .iter()
+        .map(|node| {
+            let root = &nodes[node.root];
+            let gravity = node.x * (root.x.inverse() * root.a);
+            node.v + dt * (node.a + -1. * gravity)
+        })
+        .collect();
+
+    // response of every row to a unit impulse on every other row
+    let mut responses = Vec::with_capacity(n_rows);
+    let mut a_matrix = vec![vec![0.; n_rows]; n_rows];
+    for (j, row) in rows.iter().enumerate() {
+        let (qdd, a) = test_force_response(&nodes, &row.forces());
+        for (i, other_row) in rows.iter().enumerate() {
+            a_matrix[i][j] = other_row.velocity(&nodes, &a);
+        }
+        responses.push((qdd, a));
+    }
+    let mut b: Vec<f32> = rows
+        .iter()
+        .map(|row| row.velocity(&nodes, &free_velocity))
+        .collect();
+    for (contact_ind, target) in target.iter().enumerate() {
+        b[3 * contact_ind] -= target;
+    }
+
+    // projected gauss-seidel
+    let mut lambda = vec![0.; n_rows];
+    let row_velocity = |lambda: &[f32], i: usize| {
+        b[i] + (0..n_rows).map(|j| a_matrix[i][j] * lambda[j]).sum::<f32>()
+    };
+    for _ in 0..solver.iterations {
+        for (contact_ind, mu) in friction.iter().enumerate() {
+            let normal = 3 * contact_ind;
+            if a_matrix[normal][normal] <= 0. {
+                continue;
+            }
+            lambda[normal] =
+                (lambda[normal] - row_velocity(&lambda, normal) / a_matrix[normal][normal]).max(0.);
+
+            for tangent in [normal + 1, normal + 2] {
+                if a_matrix[tangent][tangent] > 0. {
+                    lambda[tangent] -= row_velocity(&lambda, tangent) / a_matrix[tangent][tangent];
+                }
+            }
+            // project the friction impulse onto the cone
+            let limit = mu * lambda[normal];
+            let magnitude = (lambda[normal + 1].powi(2) + lambda[normal + 2].powi(2)).sqrt();
+            if magnitude > limit {
+                let scale = if magnitude > 0. {
+                    limit / magnitude
+                } else {
+                    0.
+                };
+                lambda[normal + 1] *= scale;
+                lambda[normal + 2] *= scale;
+            }
+        }
+    }
+
+    // apply, the dynamics are linear in the external forces so the responses superimpose
+    for (j, row) in rows.iter().enumerate() {
+        let force_scale = lambda[j] / dt;
+        if force_scale == 0. {
+            continue;
+        }
+        let (qdd, a) = &responses[j];
+        for (ind, node) in nodes.iter().enumerate() {
+            if qdd[ind] == 0. {
+                continue;
+            }
+            if let Ok(mut joint) = joint_query.get_mut(node.entity) {
+                joint.qdd += force_scale * qdd[ind];
+                joint.a = joint.a + force_scale * a[ind];
+            }
+        }
+        for (ind, force) in row.forces() {
+            if let Ok(mut joint) = joint_query.get_mut(nodes[ind].entity) {
+                joint.f_ext += force_scale * force;
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::{collections::HashMap, fmt};
+
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use bevy_integrator::integrator::{PhysicsSchedule, PhysicsState, Stateful};
+
+use crate::{
+    joint::{Joint, JointState},
+    simulation::{PostStepSchedule, PostStepSet, SimulationControl},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhysicsPhase {
+    Loop1,
+    Forces,
+    Loop23,
+}
+
+impl fmt::Display for PhysicsPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhysicsPhase::Loop1 => write!(f, "loop_1"),
+            PhysicsPhase::Forces => write!(f, "forces"),
+            PhysicsPhase::Loop23 => write!(f, "loop_23"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum DivergenceAction {
+    #[default]
+    Halt, // pause the simulation, leaving the bad state in place for inspection
+    Rollback, // restore the last good state, then pause
+}
+
+#[derive(Resource)]
+pub struct DivergenceConfig {
+    pub action: DivergenceAction,
+    pub max_q: f32,
+    pub max_qd: f32,
+    pub max_qdd: f32,
+}
+
+impl Default for DivergenceConfig {
+    fn default() -> Self {
+        Self {
+            action: DivergenceAction::Halt,
+            max_q: 1.0e6,
+            max_qd: 1.0e4,
+            max_qdd: 1.0e6,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DivergenceEvent {
+    pub joint: String,
+    pub phase: PhysicsPhase,
+    pub q: f32,
+    pub qd: f32,
+    pub qdd: f32,
+}
+
+// set by the checks inside the physics schedule, handled by the simulation clock
+#[derive(Resource, Default)]
+pub struct DivergenceStatus {
+    pub detected: bool,
+}
+
+// joint states at the end of the last step that passed all checks
+#[derive(Resource, Default)]
+pub struct LastGoodState {
+    pub states: HashMap<Entity, JointState>,
+    pub time: f32,
+}
+
+// state outside the joints that changes from step to step (tread temperature, gear, state of
+// charge). Components and resources registered with RollbackAppExt are saved with the joint
+// states after every good step and restored with them on rollback.
+pub trait RollbackState {
+    type State: Clone + Send + Sync + 'static;
+    fn save(&self) -> Self::State;
+    fn restore(&mut self, state: &Self::State);
+}
+
+#[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct SaveStateSchedule;
+
+#[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct RestoreStateSchedule;
+
+#[derive(Resource)]
+struct SavedComponents<C: RollbackState + Send + Sync + 'static> {
+    states: HashMap<Entity, C::State>,
+}
+
+#[derive(Resource)]
+struct SavedResource<R: RollbackState + Send + Sync + 'static> {
+    state: Option<R::State>,
+}
+
+fn save_components<C: Component + RollbackState>(
+    components: Query<(Entity, &C)>,
+    mut saved: ResMut<SavedComponents<C>>,
+) {
+    saved.states = components
+        .iter()
+        .map(|(entity, component)| (entity, component.save()))
+        .collect();
+}
+
+fn restore_components<C: Component + RollbackState>(
+    mut components: Query<(Entity, &mut C)>,
+    saved: Res<SavedComponents<C>>,
+) {
+    for (entity, mut component) in components.iter_mut() {
+        if let Some(state) = saved.states.get(&entity) {
+            component.restore(state);
+        }
+    }
+}
+
+fn save_resource<R: Resource + RollbackState>(
+    resource: Option<Res<R>>,
+    mut saved: ResMut<SavedResource<R>>,
+) {
+    saved.state = resource.map(|resource| resource.save());
+}
+
+fn restore_resource<R: Resource + RollbackState>(
+    resource: Option<ResMut<R>>,
+    saved: Res<SavedResource<R>>,
+) {
+    if let (Some(mut resource), Some(state)) = (resource, saved.state.as_ref()) {
+        resource.restore(state);
+    }
+}
+
+// register after DivergencePlugin, it owns the save and restore schedules
+pub trait RollbackAppExt {
+    fn add_rollback_component<C: Component + RollbackState>(&mut self) -> &mut Self;
+    fn add_rollback_resource<R: Resource + RollbackState>(&mut self) -> &mut Self;
+}
+
+impl RollbackAppExt for App {
+    fn add_rollback_component<C: Component + RollbackState>(&mut self) -> &mut Self {
+        self.insert_resource(SavedComponents::<C> {
+            states: HashMap::new(),
+        })
+        .add_system(save_components::<C>.in_schedule(SaveStateSchedule))
+        .add_system(restore_components::<C>.in_schedule(RestoreStateSchedule))
+    }
+
+    fn add_rollback_resource<R: Resource + RollbackState>(&mut self) -> &mut Self {
+        self.insert_resource(SavedResource::<R> { state: None })
+            .add_system(save_resource::<R>.in_schedule(SaveStateSchedule))
+            .add_system(restore_resource::<R>.in_schedule(RestoreStateSchedule))
+    }
+}
+
+// run condition: skip force evaluation and post step updates once a bad state has been seen, so
+// nothing downstream runs on non-finite values
+pub fn not_diverged(status: Option<Res<DivergenceStatus>>) -> bool {
+    status.map_or(true, |status| !status.detected)
+}
+
+fn bad_value(value: f32, limit: f32) -> bool {
+    !value.is_finite() || value.abs() > limit
+}
+
+fn check_joints(
+    phase: PhysicsPhase,
+    joints: &Query<&Joint>,
+    config: &DivergenceConfig,
+    status: &mut DivergenceStatus,
+    events: &mut EventWriter<DivergenceEvent>,
+) {
+    // only report the first joint, everything downstream of it is garbage anyway
+    if status.detected {
+        return;
+    }
+    for joint in joints.iter() {
+        let diverged = match phase {
+            PhysicsPhase::Loop1 => {
+                bad_value(joint.q, config.max_q)
+                    || bad_value(joint.qd, config.max_qd)
+                    || !joint
+                        .v
+                        .v
+                        .iter()
+                        .chain(joint.v.w.iter())
+                        .all(|x| x.is_finite())
+            }
+            PhysicsPhase::Forces => {
+                !joint.tau.is_finite()
+                    || !joint
+                        .f_ext
+                        .f
+                        .iter()
+                        .chain(joint.f_ext.m.iter())
+                        .all(|x| x.is_finite())
+            }
+            PhysicsPhase::Loop23 => bad_value(joint.qdd, config.max_qdd),
+        };
+
+        if diverged {
+            status.detected = true;
+            events.send(DivergenceEvent {
+                joint: joint.name.clone(),
+                phase,
+                q: joint.q,
+                qd: joint.qd,
+                qdd: joint.qdd,
+            });
+            warn!(
+                "physics diverged in {} at joint {}: q = {}, qd = {}, qdd = {}",
+                phase, joint.name, joint.q, joint.qd, joint.qdd
+            );
+            return;
+        }
+    }
+}
+
+pub fn check_loop_1(
+    joints: Query<&Joint>,
+    config: Res<DivergenceConfig>,
+    mut status: ResMut<DivergenceStatus>,
+    mut events: EventWriter<DivergenceEvent>,
+) {
+    check_joints(
+        PhysicsPhase::Loop1,
+        &joints,
+        &config,
+        &mut status,
+        &mut events,
+    );
+}
+
+pub fn check_forces(
+    joints: Query<&Joint>,
+    config: Res<DivergenceConfig>,
+    mut status: ResMut<DivergenceStatus>,
+    mut events: EventWriter<DivergenceEvent>,
+) {
+    check_joints(
+        PhysicsPhase::Forces,
+        &joints,
+        &config,
+        &mut status,
+        &mut events,
+    );
+}
+
+pub fn check_loop_23(
+    joints: Query<&Joint>,
+    config: Res<DivergenceConfig>,
+    mut status: ResMut<DivergenceStatus>,
+    mut events: EventWriter<DivergenceEvent>,
+) {
+    check_joints(
+        PhysicsPhase::Loop23,
+        &joints,
+        &config,
+        &mut status,
+        &mut events,
+    );
+}
+
+// called by the simulation clock after each step. Returns false if stepping should stop.
+pub fn handle_divergence(world: &mut World) -> bool {
+    // nothing to check without the DivergencePlugin
+    let Some(status) = world.get_resource::<DivergenceStatus>() else {
+        return true;
+    };
+    let elapsed = world.resource::<SimulationControl>().elapsed;
+
+    if !status.detected {
+        // remember this state in case a later step blows up
+        let mut states = HashMap::new();
+        for (entity, joint) in world.query::<(Entity, &Joint)>().iter(world) {
+            states.insert(entity, JointState::from_joint(joint));
+        }
+        let mut last_good = world.resource_mut::<LastGoodState>();
+        last_good.states = states;
+        last_good.time = elapsed;
+        world.run_schedule(SaveStateSchedule);
+        return true;
+    }
+
+    world.resource_mut::<SimulationControl>().paused = true;
+
+    let rollback = world.resource::<DivergenceConfig>().action == DivergenceAction::Rollback;
+    if rollback && world.resource::<LastGoodState>().states.is_empty() {
+        warn!("no good state captured yet, halting without rolling back");
+    } else if rollback {
+        let states = world.resource::<LastGoodState>().states.clone();
+        let time = world.resource::<LastGoodState>().time;
+        for (entity, mut joint) in world.query::<(Entity, &mut Joint)>().iter_mut(world) {
+            if let Some(state) = states.get(&entity) {
+                joint.set_state(state);
+            }
+        }
+        if let Some(mut physics_state) = world.get_resource_mut::<PhysicsState<Joint>>() {
+            for (entity, state) in states.iter() {
+                physics_state.states.insert(*entity, state.clone());
+            }
+        }
+        world.run_schedule(RestoreStateSchedule);
+        world.resource_mut::<SimulationControl>().elapsed = time;
+        info!("rolled back to the last good state at t = {}", time);
+
+        // re-evaluate the model so transforms and accelerations match the restored state
+        world.resource_mut::<DivergenceStatus>().detected = false;
+        world.run_schedule(PhysicsSchedule);
+    }
+
+    // leave the flag cleared so stepping can resume once the user un-pauses
+    world.resource_mut::<DivergenceStatus>().detected = false;
+    false
+}
+
+// divergence checks, halt or rollback. Add after the SimulationPlugin, the post step updates
+// are skipped once a bad state has been seen.
+pub struct DivergencePlugin;
+
+impl Plugin for DivergencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_schedule(SaveStateSchedule, Schedule::new())
+            .add_schedule(RestoreStateSchedule, Schedule::new())
+            .init_resource::<DivergenceConfig>()
+            .init_resource::<DivergenceStatus>()
+            .init_resource::<LastGoodState>()
+            .add_event::<DivergenceEvent>()
+            .edit_schedule(PostStepSchedule, |schedule| {
+                schedule.configure_set(PostStepSet::Update.run_if(not_diverged));
+            });
+    }
+}
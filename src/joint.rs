@@ -21,6 +21,11 @@ pub enum JointType {
 #[derive(Component, Default, Debug)]
 pub struct Base;
 
+// extra first order states a joint carries for the components attached to it (tire tread
+// deflection, brake pads). The force systems write their rates to aux_d every stage, so they are
+// integrated, saved and rolled back together with q and qd.
+pub const AUX_STATES: usize = 4;
+
 #[derive(Component, Default, Debug)]
 pub struct Joint {
     pub joint_type: JointType,
@@ -35,6 +40,8 @@ pub struct Joint {
     pub q: f32,
     pub qd: f32,
     pub qdd: f32,
+    pub aux: [f32; AUX_STATES],
+    pub aux_d: [f32; AUX_STATES],
 
     // common parameters
     pub xl: Xform,
@@ -184,28 +191,33 @@ impl Stateful for Joint {
         Self::State {
             q: self.q,
             qd: self.qd,
+            aux: self.aux,
         }
     }
 
     fn set_state(&mut self, state: &Self::State) {
         self.q = state.q;
         self.qd = state.qd;
+        self.aux = state.aux;
     }
 
     fn get_dstate(&self) -> Self::State {
         Self::State {
             q: self.qd,
             qd: self.qdd,
+            aux: self.aux_d,
         }
     }
 
     fn set_dstate(&mut self, dstate: Self::State) {
         self.qd = dstate.q;
         self.qdd = dstate.qd;
+        self.aux_d = dstate.aux;
     }
 
     fn reset(&mut self) {
         self.qdd = 0.;
+        self.aux_d = [0.; AUX_STATES];
         self.f_ext = Force::zero();
         self.tau = 0.;
     }
@@ -219,26 +231,36 @@ impl Stateful for Joint {
 pub struct JointState {
     pub q: f32,
     pub qd: f32,
+    pub aux: [f32; AUX_STATES],
 }
 
 impl JointState {
     pub fn new(q: f32, qd: f32) -> Self {
-        Self { q, qd }
+        Self {
+            q,
+            qd,
+            aux: [0.; AUX_STATES],
+        }
     }
     pub fn zero() -> Self {
         Self::new(0., 0.)
     }
     pub fn from_joint(joint: &Joint) -> Self {
-        Self::new(joint.q, joint.qd)
+        joint.get_state()
     }
 }
 
 impl Add for JointState {
     type Output = JointState;
     fn add(self, other: JointState) -> JointState {
+        let mut aux = self.aux;
+        for (a, b) in aux.iter_mut().zip(other.aux) {
+            *a += b;
+        }
         JointState {
             q: self.q + other.q,
             qd: self.qd + other.qd,
+            aux,
         }
     }
 }
@@ -249,6 +271,7 @@ impl Mul<f32> for JointState {
         JointState {
             q: self.q * other,
             qd: self.qd * other,
+            aux: self.aux.map(|a| a * other),
         }
     }
 }
@@ -1,7 +1,17 @@
 pub mod algorithms;
 pub mod car;
+pub mod collision;
+pub mod contact_solver;
+pub mod divergence;
 pub mod joint;
+pub mod lookup;
 pub mod mesh;
+pub mod physics_diagnostics;
 pub mod serialize;
+pub mod simulation;
 pub mod structure;
+pub mod surface;
 pub mod sva;
+pub mod terrain;
+pub mod trimesh;
+pub mod wind;
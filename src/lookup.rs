@@ -0,0 +1,265 @@
+// n dimensional table on a rectangular grid, with multilinear interpolation. Outside the grid
+// the values are clamped to the edge.
+#[derive(Debug, Clone)]
+pub struct LookupTable {
+    pub axes: Vec<Vec<f32>>, // strictly increasing breakpoints for each input
+    pub values: Vec<f32>,    // row major, the last axis changes fastest
+}
+
+// most inputs a table can have, so a lookup needs no allocation
+const MAX_DIMENSIONS: usize = 8;
+
+impl LookupTable {
+    // the axes must be non-empty and strictly increasing, with one value per grid point
+    pub fn new(axes: Vec<Vec<f32>>, values: Vec<f32>) -> Result<Self, String> {
+        if axes.is_empty() || axes.len() > MAX_DIMENSIONS {
+            return Err(format!(
+                "lookup table needs 1 to {} axes, got {}",
+                MAX_DIMENSIONS,
+                axes.len()
+            ));
+        }
+        for (dim, axis) in axes.iter().enumerate() {
+            if axis.is_empty() {
+                return Err(format!("lookup table axis {} is empty", dim));
+            }
+            if axis.iter().any(|x| !x.is_finite()) {
+                return Err(format!("lookup table axis {} is not finite", dim));
+            }
+            if axis.windows(2).any(|pair| pair[1] <= pair[0]) {
+                return Err(format!(
+                    "lookup table axis {} is not strictly increasing",
+                    dim
+                ));
+            }
+        }
+        let size: usize = axes.iter().map(|axis| axis.len()).product();
+        if values.len() != size {
+            return Err(format!(
+                "lookup table has {} values for {} grid points",
+                values.len(),
+                size
+            ));
+        }
+        Ok(Self { axes, values })
+    }
+
+    // one dimensional curve through (x, y) points
+    pub fn curve(points: &[[f32; 2]]) -> Result<Self, String> {
+        Self::new(
+            vec![points.iter().map(|point| point[0]).collect()],
+            points.iter().map(|point| point[1]).collect(),
+        )
+    }
+
+    // table on the given axes from scattered samples (input point, value). Each sample is spread
+    // over the corners of its cell with the interpolation weights, and each breakpoint takes the
+    // weighted mean of its samples. Breakpoints without samples are filled by interpolating
+    // along each axis in turn.
+    pub fn fit(axes: Vec<Vec<f32>>, samples: &[(Vec<f32>, f32)]) -> Result<Self, String> {
+        let size = axes.iter().map(|axis| axis.len()).product();
+        let mut table = Self::new(axes, vec![0.; size])?;
+        let mut sums = vec![0.; size];
+        let mut weights = vec![0.; size];
+        for (point, value) in samples {
+            if point.len() != table.axes.len() {
+                return Err(format!(
+                    "sample has {} inputs, the table {}",
+                    point.len(),
+                    table.axes.len()
+                ));
+            }
+            table.for_each_corner(point, |index, weight| {
+                sums[index] += weight * value;
+                weights[index] += weight;
+            });
+        }
+        table.values = sums;
+        let mut filled: Vec<bool> = weights.iter().map(|weight| *weight > 0.).collect();
+        if !filled.contains(&true) {
+            return Err("no samples to fit the table to".to_string());
+        }
+        for (value, weight) in table.values.iter_mut().zip(&weights) {
+            if *weight > 0. {
+                *value /= weight;
+            }
+        }
+
+        // row major strides, the last axis changes fastest
+        let mut strides = vec![1; table.axes.len()];
+        for dim in (0..table.axes.len().saturating_sub(1)).rev() {
+            strides[dim] = strides[dim + 1] * table.axes[dim + 1].len();
+        }
+        for (dim, axis) in table.axes.iter().enumerate() {
+            let stride = strides[dim];
+            let line_starts =
+                (0..table.values.len()).filter(|start| start / stride % axis.len() == 0);
+            let mut newly_filled = Vec::new();
+            for start in line_starts {
+                let line: Vec<usize> = (0..axis.len()).map(|k| start + k * stride).collect();
+                let known: Vec<usize> = (0..axis.len()).filter(|k| filled[line[*k]]).collect();
+                if known.is_empty() {
+                    continue;
+                }
+                for k in (0..axis.len()).filter(|k| !filled[line[*k]]) {
+                    // neighbouring known breakpoints, the value is held past the last one
+                    let upper = known.partition_point(|known| *known < k);
+                    let value = match (upper.checked_sub(1).map(|i| known[i]), known.get(upper)) {
+                        (Some(a), Some(&b)) => {
+                            let fraction = (axis[k] - axis[a]) / (axis[b] - axis[a]);
+                            table.values[line[a]]
+                                + fraction * (table.values[line[b]] - table.values[line[a]])
+                        }
+                        (Some(a), None) => table.values[line[a]],
+                        (None, Some(&b)) => table.values[line[b]],
+                        (None, None) => unreachable!(),
+                    };
+                    table.values[line[k]] = value;
+                    newly_filled.push(line[k]);
+                }
+            }
+            for index in newly_filled {
+                filled[index] = true;
+            }
+        }
+        Ok(table)
+    }
+
+    // index of the lower breakpoint and the fraction towards the next one. NaN inputs go to the
+    // first breakpoint.
+    fn locate(axis: &[f32], x: f32) -> (usize, f32) {
+        if axis.len() == 1 || x.is_nan() || x <= axis[0] {
+            return (0, 0.);
+        }
+        let last = axis.len() - 1;
+        if x >= axis[last] {
+            return (last - 1, 1.);
+        }
+        let upper = axis.partition_point(|breakpoint| *breakpoint <= x);
+        let lower = upper - 1;
+        (lower, (x - axis[lower]) / (axis[upper] - axis[lower]))
+    }
+
+    pub fn interpolate(&self, point: &[f32]) -> f32 {
+        let mut value = 0.;
+        self.for_each_corner(point, |index, weight| value += weight * self.values[index]);
+        value
+    }
+
+    // value index and interpolation weight of each corner of the cell around the point
+    fn for_each_corner(&self, point: &[f32], mut corner_fn: impl FnMut(usize, f32)) {
+        assert_eq!(point.len(), self.axes.len(), "lookup point dimension");
+        let dimensions = self.axes.len();
+        let mut cells = [(0, 0.); MAX_DIMENSIONS];
+        for (cell, (axis, x)) in cells.iter_mut().zip(self.axes.iter().zip(point)) {
+            *cell = Self::locate(axis, *x);
+        }
+
+        // the 2^n corners of the cell, bit dim of corner picks the upper breakpoint
+        for corner in 0..1_usize << dimensions {
+            let mut weight = 1.;
+            let mut index = 0;
+            for (dim, (lower, fraction)) in cells[..dimensions].iter().enumerate() {
+                let upper = corner >> dim & 1 == 1;
+                if self.axes[dim].len() == 1 {
+                    if upper {
+                        weight = 0.;
+                        break;
+                    }
+                    continue; // index * 1 + 0
+                }
+                weight *= if upper { *fraction } else { 1. - fraction };
+                index = index * self.axes[dim].len() + lower + upper as usize;
+            }
+            if weight != 0. {
+                corner_fn(index, weight);
+            }
+        }
+    }
+
+    pub fn interpolate_1d(&self, x: f32) -> f32 {
+        self.interpolate(&[x])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_interpolates_and_clamps() {
+        let curve = LookupTable::curve(&[[0., 0.], [1., 10.], [3., 20.]]).unwrap();
+        assert_eq!(curve.interpolate_1d(0.5), 5.);
+        assert_eq!(curve.interpolate_1d(2.), 15.);
+        assert_eq!(curve.interpolate_1d(-1.), 0.);
+        assert_eq!(curve.interpolate_1d(5.), 20.);
+        assert_eq!(curve.interpolate_1d(f32::NAN), 0.);
+    }
+
+    #[test]
+    fn bilinear_is_exact_for_a_plane() {
+        let axes = vec![vec![0., 1., 2.], vec![0., 2.]];
+        let plane = |x: f32, y: f32| 1. + 2. * x - 3. * y;
+        let values = vec![
+            plane(0., 0.),
+            plane(0., 2.),
+            plane(1., 0.),
+            plane(1., 2.),
+            plane(2., 0.),
+            plane(2., 2.),
+        ];
+        let table = LookupTable::new(axes, values).unwrap();
+        for (x, y) in [(0.5, 0.5), (1.25, 1.5), (2., 2.), (0., 1.)] {
+            assert!((table.interpolate(&[x, y]) - plane(x, y)).abs() < 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn single_breakpoint_axis_is_constant() {
+        let table = LookupTable::new(vec![vec![0., 1.], vec![5.]], vec![1., 3.]).unwrap();
+        assert_eq!(table.interpolate(&[0.5, -100.]), 2.);
+    }
+
+    #[test]
+    fn new_rejects_bad_tables() {
+        assert!(LookupTable::new(vec![], vec![]).is_err());
+        assert!(LookupTable::new(vec![vec![]], vec![]).is_err());
+        assert!(LookupTable::curve(&[[0., 0.], [0., 1.]]).is_err());
+        assert!(LookupTable::curve(&[[1., 0.], [0., 1.]]).is_err());
+        assert!(LookupTable::new(vec![vec![0., 1.]], vec![0.]).is_err());
+        assert!(LookupTable::new(vec![vec![0., f32::NAN]], vec![0., 1.]).is_err());
+    }
+
+    #[test]
+    fn fit_recovers_grid_samples() {
+        let axes = vec![vec![0., 1., 2.], vec![0., 1.]];
+        let plane = |x: f32, y: f32| 2. * x + y;
+        let samples: Vec<(Vec<f32>, f32)> = [0., 1., 2.]
+            .iter()
+            .flat_map(|x| [0., 1.].map(|y| (vec![*x, y], plane(*x, y))))
+            .collect();
+        let table = LookupTable::fit(axes, &samples).unwrap();
+        for (x, y) in [(0., 0.), (0.5, 0.25), (1.5, 1.), (2., 0.5)] {
+            assert!((table.interpolate(&[x, y]) - plane(x, y)).abs() < 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn fit_fills_breakpoints_without_samples() {
+        // samples only at the ends, the middle breakpoint is interpolated
+        let samples = [(vec![0.], 1.), (vec![2.], 5.)];
+        let table = LookupTable::fit(vec![vec![0., 1., 2.]], &samples).unwrap();
+        assert_eq!(table.values, vec![1., 3., 5.]);
+
+        // past the last sample the value is held
+        let samples = [(vec![0.], 1.)];
+        let table = LookupTable::fit(vec![vec![0., 1., 2.]], &samples).unwrap();
+        assert_eq!(table.values, vec![1., 1., 1.]);
+    }
+
+    #[test]
+    fn fit_rejects_bad_samples() {
+        assert!(LookupTable::fit(vec![vec![0., 1.]], &[]).is_err());
+        assert!(LookupTable::fit(vec![vec![0., 1.]], &[(vec![0., 1.], 1.)]).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::serialize::{MeshDef, MeshTypeDef};
 use bevy::prelude::{shape, Mesh as BevyMesh};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BoxMesh {
     pub min_x: f32,
     pub max_x: f32,
@@ -34,19 +34,97 @@ impl BoxMesh {
     }
 }
 
-#[derive(Debug)]
+// cylinder with its axis along y (same as bevy)
+#[derive(Debug, Clone)]
+pub struct CylinderMesh {
+    pub height: f32,
+    pub radius: f32,
+}
+
+impl CylinderMesh {
+    pub fn new(height: f32, radius: f32) -> Self {
+        Self { height, radius }
+    }
+    pub fn to_bevy_mesh(self) -> BevyMesh {
+        BevyMesh::from(shape::Cylinder {
+            height: self.height,
+            radius: self.radius,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SphereMesh {
+    pub radius: f32,
+}
+
+impl SphereMesh {
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+    pub fn to_bevy_mesh(self) -> BevyMesh {
+        BevyMesh::from(shape::UVSphere {
+            radius: self.radius,
+            ..Default::default()
+        })
+    }
+}
+
+// capsule with its axis along y (same as bevy). length is the distance between the cap centers
+#[derive(Debug, Clone)]
+pub struct CapsuleMesh {
+    pub length: f32,
+    pub radius: f32,
+}
+
+impl CapsuleMesh {
+    pub fn new(length: f32, radius: f32) -> Self {
+        Self { length, radius }
+    }
+    pub fn to_bevy_mesh(self) -> BevyMesh {
+        BevyMesh::from(shape::Capsule {
+            depth: self.length,
+            radius: self.radius,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Mesh {
     Box(BoxMesh),
-    // Cylinder,
+    Cylinder(CylinderMesh),
+    Sphere(SphereMesh),
+    Capsule(CapsuleMesh),
 }
 
 impl Mesh {
     pub fn from_mesh_def(mesh_def: &MeshDef) -> Self {
-        match mesh_def.mesh_type {
+        Self::from_mesh_type_def(&mesh_def.mesh_type)
+    }
+
+    pub fn from_mesh_type_def(mesh_type_def: &MeshTypeDef) -> Self {
+        match *mesh_type_def {
             MeshTypeDef::Box {
                 half_extents: [x, y, z],
             } => Self::Box(BoxMesh::new(-x, x, -y, y, -z, z)),
-            _ => unimplemented!(),
+            MeshTypeDef::Cylinder { height, radius } => {
+                Self::Cylinder(CylinderMesh::new(height, radius))
+            }
+            MeshTypeDef::Sphere { radius } => Self::Sphere(SphereMesh::new(radius)),
+            MeshTypeDef::Capsule { length, radius } => {
+                Self::Capsule(CapsuleMesh::new(length, radius))
+            }
+        }
+    }
+
+    pub fn to_bevy_mesh(self) -> BevyMesh {
+        match self {
+            Mesh::Box(mesh) => mesh.to_bevy_mesh(),
+            Mesh::Cylinder(mesh) => mesh.to_bevy_mesh(),
+            Mesh::Sphere(mesh) => mesh.to_bevy_mesh(),
+            Mesh::Capsule(mesh) => mesh.to_bevy_mesh(),
         }
     }
 }
@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+
+use crate::{
+    divergence::PhysicsPhase,
+    joint::{Base, Joint},
+    simulation::SimulationControl,
+};
+
+// wall clock time spent in each phase of the physics schedule since the last frame
+#[derive(Resource, Default)]
+pub struct PhysicsTimings {
+    phase_start: Option<Instant>,
+    pub step: Duration, // whole integrator steps, including the integrator itself
+    pub loop_1: Duration,
+    pub forces: Duration,
+    pub loop_23: Duration,
+    pub stages: u32, // number of times the physics schedule ran (integrator stages)
+}
+
+impl PhysicsTimings {
+    fn end_phase(&mut self, phase: PhysicsPhase) {
+        let now = Instant::now();
+        if let Some(start) = self.phase_start {
+            let elapsed = now - start;
+            match phase {
+                PhysicsPhase::Loop1 => self.loop_1 += elapsed,
+                PhysicsPhase::Forces => self.forces += elapsed,
+                PhysicsPhase::Loop23 => self.loop_23 += elapsed,
+            }
+        }
+        self.phase_start = Some(now);
+    }
+
+    fn reset(&mut self) {
+        self.step = Duration::ZERO;
+        self.loop_1 = Duration::ZERO;
+        self.forces = Duration::ZERO;
+        self.loop_23 = Duration::ZERO;
+        self.stages = 0;
+    }
+}
+
+// the timer systems are part of the physics schedule, they do nothing unless the plugin is added
+pub fn start_stage_timer(timings: Option<ResMut<PhysicsTimings>>) {
+    if let Some(mut timings) = timings {
+        timings.stages += 1;
+        timings.phase_start = Some(Instant::now());
+    }
+}
+
+pub fn end_loop_1_timer(timings: Option<ResMut<PhysicsTimings>>) {
+    if let Some(mut timings) = timings {
+        timings.end_phase(PhysicsPhase::Loop1);
+    }
+}
+
+pub fn end_forces_timer(timings: Option<ResMut<PhysicsTimings>>) {
+    if let Some(mut timings) = timings {
+        timings.end_phase(PhysicsPhase::Forces);
+    }
+}
+
+pub fn end_loop_23_timer(timings: Option<ResMut<PhysicsTimings>>) {
+    if let Some(mut timings) = timings {
+        timings.end_phase(PhysicsPhase::Loop23);
+    }
+}
+
+pub struct PhysicsDiagnosticsPlugin;
+
+impl Plugin for PhysicsDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PhysicsTimings>()
+            .add_startup_system(Self::setup_system)
+            .add_system(Self::diagnostic_system);
+    }
+}
+
+impl PhysicsDiagnosticsPlugin {
+    pub const STEP_TIME: DiagnosticId =
+        DiagnosticId::from_u128(160937540275237811262151298392838702017);
+    pub const LOOP_1_TIME: DiagnosticId =
+        DiagnosticId::from_u128(298130713994614431567215869186120397541);
+    pub const FORCES_TIME: DiagnosticId =
+        DiagnosticId::from_u128(66730468419180442379936815512196381739);
+    pub const LOOP_23_TIME: DiagnosticId =
+        DiagnosticId::from_u128(229718245936178125406557932741826104313);
+    pub const STEPS_PER_FRAME: DiagnosticId =
+        DiagnosticId::from_u128(117372935587340713442880116315412874029);
+    pub const STAGES_PER_STEP: DiagnosticId =
+        DiagnosticId::from_u128(21388394003437211968203618339128779341);
+    pub const REAL_TIME_FACTOR: DiagnosticId =
+        DiagnosticId::from_u128(190815730925063453720935713734290719453);
+    pub const JOINT_COUNT: DiagnosticId =
+        DiagnosticId::from_u128(265442616451913370911407640128592386327);
+    pub const BASE_COUNT: DiagnosticId =
+        DiagnosticId::from_u128(88251709830402937393728232120460146717);
+
+    pub fn setup_system(mut diagnostics: ResMut<Diagnostics>) {
+        diagnostics
+            .add(Diagnostic::new(Self::STEP_TIME, "physics_step_time", 20).with_suffix("ms"));
+        diagnostics
+            .add(Diagnostic::new(Self::LOOP_1_TIME, "physics_loop_1_time", 20).with_suffix("ms"));
+        diagnostics
+            .add(Diagnostic::new(Self::FORCES_TIME, "physics_forces_time", 20).with_suffix("ms"));
+        diagnostics
+            .add(Diagnostic::new(Self::LOOP_23_TIME, "physics_loop_23_time", 20).with_suffix("ms"));
+        diagnostics.add(Diagnostic::new(
+            Self::STEPS_PER_FRAME,
+            "physics_steps_per_frame",
+            20,
+        ));
+        diagnostics.add(Diagnostic::new(
+            Self::STAGES_PER_STEP,
+            "physics_stages_per_step",
+            20,
+        ));
+        diagnostics.add(Diagnostic::new(
+            Self::REAL_TIME_FACTOR,
+            "physics_real_time_factor",
+            20,
+        ));
+        diagnostics.add(
+            Diagnostic::new(Self::JOINT_COUNT, "physics_joint_count", 1).with_smoothing_factor(0.0),
+        );
+        diagnostics.add(
+            Diagnostic::new(Self::BASE_COUNT, "physics_base_count", 1).with_smoothing_factor(0.0),
+        );
+    }
+
+    pub fn diagnostic_system(
+        mut diagnostics: ResMut<Diagnostics>,
+        mut timings: ResMut<PhysicsTimings>,
+        simulation: Option<Res<SimulationControl>>,
+        joints: Query<(), With<Joint>>,
+        bases: Query<(), With<Base>>,
+    ) {
+        diagnostics.add_measurement(Self::JOINT_COUNT, || joints.iter().count() as f64);
+        diagnostics.add_measurement(Self::BASE_COUNT, || bases.iter().count() as f64);
+
+        // no simulation clock in playback, only the model size is measured
+        let Some(simulation) = simulation else {
+            timings.reset();
+            return;
+        };
+        let steps = simulation.steps_last_frame;
+        diagnostics.add_measurement(Self::STEPS_PER_FRAME, || steps as f64);
+        diagnostics.add_measurement(Self::REAL_TIME_FACTOR, || {
+            simulation.real_time_factor as f64
+        });
+
+        // per step values are meaningless when paused
+        if steps > 0 {
+            let per_step_ms = |duration: Duration| duration.as_secs_f64() * 1000. / steps as f64;
+            let loop_1 = per_step_ms(timings.loop_1);
+            let forces = per_step_ms(timings.forces);
+            let loop_23 = per_step_ms(timings.loop_23);
+            let step = per_step_ms(timings.step);
+            diagnostics.add_measurement(Self::LOOP_1_TIME, || loop_1);
+            diagnostics.add_measurement(Self::FORCES_TIME, || forces);
+            diagnostics.add_measurement(Self::LOOP_23_TIME, || loop_23);
+            diagnostics.add_measurement(Self::STEP_TIME, || step);
+            diagnostics.add_measurement(Self::STAGES_PER_STEP, || {
+                timings.stages as f64 / steps as f64
+            });
+        }
+
+        timings.reset();
+    }
+}
@@ -7,6 +7,8 @@ pub struct ModelDef {
     pub joints: Vec<JointDef>,
     pub name: String,
     pub systems: Vec<SystemDef>,
+    #[serde(default)]
+    pub wind: Option<WindDef>, // still air if not given
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -41,11 +43,12 @@ pub struct MeshDef {
     pub transform: TransformDef,
     pub color: [f32; 4],
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MeshTypeDef {
     Box { half_extents: [f32; 3] },
     Cylinder { height: f32, radius: f32 },
-    // Sphere { radius: f32 },
+    Sphere { radius: f32 },
+    Capsule { length: f32, radius: f32 },
     // Mesh { filename: String },
 }
 
@@ -70,10 +73,25 @@ pub struct SystemDef {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SystemTypeDef {
     Steering(SteeringDef),
+    SteeringRack(SteeringRackDef),
     Drive(DrivenWheelDef),
+    Powertrain(PowertrainDef),
+    Differential(DifferentialDef),
+    ElectricDrive(ElectricDriveDef),
     Brake(BrakeWheelDef),
+    BrakeHydraulics(BrakeHydraulicsDef),
+    DiscBrake(BrakeDef),
     Suspension(SuspensionDef),
+    AntiRollBar(AntiRollBarDef),
+    SuspensionKinematics(SuspensionKinematicsDef),
     TireContact(TireContactDef),
+    TireRelaxation(TireRelaxationDef),
+    TireCondition(TireConditionDef),
+    PacejkaTire(PacejkaTireDef),
+    TableTire(TableTireDef),
+    Aero(AeroDef),
+    DriverAssist(DriverAssistDef),
+    Collider(ColliderDef),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,6 +100,27 @@ pub struct SteeringDef {
     pub max_angle: f32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SteeringRackDef {
+    pub left: String,
+    pub right: String,
+    pub steering_ratio: f32,
+    pub max_handwheel_angle: f32,
+    pub arm_length: f32,
+    pub max_rack_travel: f32,
+    pub wheelbase: f32,
+    pub track: f32,
+    pub ackermann: f32,
+    #[serde(default)]
+    pub toe_left: f32,
+    #[serde(default)]
+    pub toe_right: f32,
+    #[serde(default)]
+    pub column_stiffness: Option<f32>, // compliant column, steer joints driven by torque
+    #[serde(default)]
+    pub column_damping: f32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DrivenWheelDef {
     pub joint: String,
@@ -90,17 +129,182 @@ pub struct DrivenWheelDef {
     pub max_power: f32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowertrainDef {
+    pub engine_joint: String, // rotational joint carrying the engine inertia
+    pub wheels: Vec<String>,  // driven wheel joints, torque split equally
+    #[serde(default)]
+    pub differential: Option<String>, // drive through this differential instead of the wheels
+    pub torque_curve: Vec<[f32; 2]>, // [rpm, Nm] at full load
+    pub idle_rpm: f32,
+    pub max_rpm: f32,
+    pub friction: [f32; 2], // Nm and Nm per rad/s
+    pub clutch_capacity: f32,
+    pub clutch_damping: f32,
+    pub gear_ratios: Vec<f32>,
+    pub reverse_ratio: f32,
+    pub final_drive: f32,
+    pub shift_mode: ShiftModeDef,
+    pub shift_time: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ShiftModeDef {
+    Manual,
+    Automatic {
+        upshift_rpm: f32,
+        downshift_rpm: f32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DifferentialDef {
+    pub name: String,
+    pub outputs: [String; 2], // wheel joints or other differentials
+    pub torque_split: f32,    // fraction of the input torque to the first output
+    pub differential_type: DifferentialTypeDef,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DifferentialTypeDef {
+    Open,
+    Locked {
+        damping: f32,
+    },
+    Viscous {
+        coefficient: f32,
+    },
+    ClutchPack {
+        preload: f32,
+        power_ramp_angle: f32,
+        coast_ramp_angle: f32,
+        friction_factor: f32,
+        damping: f32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElectricDriveDef {
+    pub wheels: Vec<String>, // driven wheel joints, torque split equally
+    #[serde(default)]
+    pub differential: Option<String>, // drive through this differential instead of the wheels
+    pub torque_curve: Vec<[f32; 2]>, // [rpm, Nm] maximum motor torque
+    pub efficiency_rpm: Vec<f32>,
+    pub efficiency_torque: Vec<f32>,
+    pub efficiency: Vec<f32>, // row per rpm, column per torque
+    pub gear_ratio: f32,
+    pub max_power: f32,
+    pub max_regen_power: f32,
+    pub regen_fade_speed: f32,
+    pub battery: BatteryDef,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatteryDef {
+    pub capacity: f32,                       // Ah
+    pub open_circuit_voltage: Vec<[f32; 2]>, // [state of charge, V]
+    pub internal_resistance: f32,
+    pub min_voltage: f32,
+    pub max_voltage: f32,
+    pub initial_state_of_charge: f32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BrakeWheelDef {
     pub joint: String,
     pub max_torque: f32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrakeHydraulicsDef {
+    pub name: String,                  // referenced by the disc brakes
+    pub pedal_pressure: Vec<[f32; 2]>, // [pedal, Pa]
+    pub bias: f32,                     // front share
+    pub time_constant: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrakeDef {
+    pub joint: String,
+    pub hydraulics: String, // brake hydraulics name
+    pub circuit: BrakeCircuitDef,
+    pub pad_friction: f32,
+    pub piston_area: f32,
+    pub effective_radius: f32,
+    #[serde(default)]
+    pub handbrake_torque: f32,
+    pub hold_stiffness: f32,
+    pub hold_damping: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BrakeCircuitDef {
+    Front,
+    Rear,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SuspensionDef {
     pub joint: String,
     pub stiffness: f32,
     pub damping: f32,
+    #[serde(default)]
+    pub spring_curve: Option<Vec<[f32; 2]>>, // [compression, N], replaces the stiffness
+    #[serde(default)]
+    pub preload: f32,
+    #[serde(default)]
+    pub ride_height: f32, // joint travel at zero spring compression
+    #[serde(default)]
+    pub bump_curve: Option<Vec<[f32; 2]>>, // [speed, N], replaces the damping in bump
+    #[serde(default)]
+    pub rebound_curve: Option<Vec<[f32; 2]>>, // [speed, N], both positive
+    #[serde(default)]
+    pub bump_stop: Option<TravelStopDef>,
+    #[serde(default)]
+    pub rebound_stop: Option<TravelStopDef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TravelStopDef {
+    pub position: f32,
+    pub stiffness: f32,
+    pub exponent: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AntiRollBarDef {
+    pub left: String, // suspension joints
+    pub right: String,
+    pub stiffness: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspensionKinematicsDef {
+    pub joint: String, // wheel joint
+    pub suspension: String,
+    #[serde(default)]
+    pub steering: Option<String>,
+    pub left: bool,
+    #[serde(default)]
+    pub camber: Option<KinematicCurveDef>, // rad, positive top out
+    #[serde(default)]
+    pub toe: Option<KinematicCurveDef>, // rad, positive toe-in
+    #[serde(default)]
+    pub track: Option<KinematicCurveDef>, // m, positive outwards
+}
+
+// travel is measured from the suspension ride height
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KinematicCurveDef {
+    Table {
+        travel: Vec<f32>,
+        steer: Vec<f32>,
+        values: Vec<f32>, // row per travel, column per steer
+    },
+    Polynomial {
+        travel: Vec<f32>, // from the constant term up
+        steer: Vec<f32>,  // from the linear term up
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -111,4 +315,211 @@ pub struct TireContactDef {
     pub damping: f32,
     pub longitudinal_stiffness: f32,
     pub lateral_stiffness: f32,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default = "default_num_points")]
+    pub num_points: usize, // tread sections across the width
+    #[serde(default)]
+    pub profile: f32, // radius drop at the tread edge
+}
+
+fn default_num_points() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TireRelaxationDef {
+    pub joint: String,
+    pub longitudinal_length: f32,
+    pub lateral_length: f32,
+    pub longitudinal_stiffness: f32,
+    pub lateral_stiffness: f32,
+    pub longitudinal_damping: f32,
+    pub lateral_damping: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TireConditionDef {
+    pub joint: String,
+    pub rolling_resistance: f32,
+    pub rolling_resistance_speed: f32,
+    pub heat_capacity: f32,
+    pub cooling: f32,
+    pub cooling_speed: f32,
+    pub heating_fraction: f32,
+    pub ambient_temperature: f32,
+    pub optimal_temperature: f32,
+    pub temperature_window: f32,
+    pub thermal_grip_loss: f32,
+    pub wear_rate: f32,
+    pub worn_grip_loss: f32,
+    #[serde(default)]
+    pub initial_temperature: Option<f32>, // the ambient temperature when not given
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacejkaTireDef {
+    pub joint: String,
+    pub tir_file: String, // path to a pacejka '02 .tir file
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableTireDef {
+    pub joint: String,
+    pub radius: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub csv_file: String, // measured forces, ttc column layout
+    #[serde(default)]
+    pub pressure: f32, // kPa, used when the table has a pressure column
+    #[serde(default)]
+    pub breakpoints: Option<TableBreakpointsDef>, // inferred from the data when not given
+}
+
+// table breakpoints for scattered measurements, in the csv units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableBreakpointsDef {
+    #[serde(default)]
+    pub slip_ratio: Option<Vec<f32>>,
+    #[serde(default)]
+    pub slip_angle: Option<Vec<f32>>, // deg
+    #[serde(default)]
+    pub vertical_force: Option<Vec<f32>>, // N
+    #[serde(default)]
+    pub camber: Option<Vec<f32>>, // deg
+    #[serde(default)]
+    pub pressure: Option<Vec<f32>>, // kPa
+}
+
+impl TableBreakpointsDef {
+    // in the TireTable input order
+    pub fn inputs(&self) -> [Option<Vec<f32>>; 5] {
+        [
+            self.slip_ratio.clone(),
+            self.slip_angle.clone(),
+            self.vertical_force.clone(),
+            self.camber.clone(),
+            self.pressure.clone(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AeroDef {
+    pub joint: String, // chassis body
+    pub drag_coefficient: f32,
+    pub lift_coefficient: f32, // negative for downforce
+    pub side_coefficient: f32,
+    pub area: f32,
+    #[serde(default = "default_air_density")]
+    pub air_density: f32,
+    pub centre_of_pressure: [f32; 3],
+    #[serde(default)]
+    pub lift_split: Option<LiftSplitDef>,
+}
+
+fn default_air_density() -> f32 {
+    1.225
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiftSplitDef {
+    pub front_fraction: f32,
+    pub front_point: [f32; 3],
+    pub rear_point: [f32; 3],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriverAssistDef {
+    pub chassis: String,
+    pub wheels: [String; 4], // front left, front right, rear left, rear right
+    pub steering: Vec<String>, // steer joints, averaged for the road wheel angle
+    pub wheel_radius: f32,
+    #[serde(default)]
+    pub abs: Option<AbsDef>,
+    #[serde(default)]
+    pub traction_control: Option<TractionControlDef>,
+    #[serde(default)]
+    pub esc: Option<EscDef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbsDef {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub target_slip: f32,
+    pub release_rate: f32, // per second
+    pub apply_rate: f32,
+    pub min_speed: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TractionControlDef {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub target_slip: f32,
+    pub cut_rate: f32, // per second
+    pub recover_rate: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscDef {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub wheelbase: f32,
+    pub understeer_gradient: f32, // rad per m/s^2
+    pub friction: f32,
+    pub gain: f32,     // Nm per rad/s of yaw rate error
+    pub deadband: f32, // rad/s
+    pub max_torque: f32,
+    pub min_speed: f32,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+// world wind, loaded with the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindDef {
+    pub velocity: [f32; 3],
+    #[serde(default)]
+    pub turbulence: Option<TurbulenceDef>,
+    #[serde(default)]
+    pub gusts: Vec<GustDef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurbulenceDef {
+    pub intensity: [f32; 3],
+    pub length_scale: f32,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GustDef {
+    pub velocity: [f32; 3],
+    pub start: f32,
+    #[serde(default)]
+    pub duration: Option<f32>, // held for good without one
+    pub rise_time: f32,
+    #[serde(default)]
+    pub region: Option<[f32; 4]>, // x min, x max, y min, y max
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColliderDef {
+    pub joint: String,
+    pub shapes: Vec<CollisionShapeDef>,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub friction: f32,
+    pub group: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollisionShapeDef {
+    pub shape: MeshTypeDef,
+    pub transform: TransformDef,
 }
@@ -0,0 +1,163 @@
+use std::time::Instant;
+
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use bevy_integrator::integrator::integrator_schedule;
+
+use crate::{
+    divergence::handle_divergence, joint::Joint, physics_diagnostics::PhysicsTimings,
+    structure::loop_1,
+};
+
+// runs once after every integrator step, for states that are not part of the joint state
+// (e.g. tread temperature, gear). The joint kinematics are updated first, so systems
+// in PostStepSet::Update see the positions and velocities at the end of the step.
+#[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct PostStepSchedule;
+
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+pub enum PostStepSet {
+    Kinematics,
+    Update,
+}
+
+fn create_post_step_schedule() -> Schedule {
+    let mut schedule = Schedule::new();
+    schedule
+        .configure_set(PostStepSet::Update.after(PostStepSet::Kinematics))
+        .add_system(loop_1.in_set(PostStepSet::Kinematics));
+    schedule
+}
+
+// controls how the physics simulation advances relative to real time
+#[derive(Resource)]
+pub struct SimulationControl {
+    pub paused: bool,
+    pub time_scale: f32,          // simulation seconds per real second
+    pub max_steps_per_frame: u32, // catch-up limit, excess time is dropped
+    pub pending_steps: u32,       // single steps requested while paused
+    pub accumulator: f32,         // simulation time not yet stepped
+    pub elapsed: f32,             // total simulation time
+    pub steps_last_frame: u32,
+    pub real_time_factor: f32, // smoothed ratio of simulation time to real time
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            time_scale: 1.,
+            max_steps_per_frame: 50,
+            pending_steps: 0,
+            accumulator: 0.,
+            elapsed: 0.,
+            steps_last_frame: 0,
+            real_time_factor: 1.,
+        }
+    }
+}
+
+impl SimulationControl {
+    // number of integrator steps to take this frame
+    pub fn steps_for_frame(&mut self, delta: f32, period: f32) -> u32 {
+        if self.paused {
+            self.accumulator = 0.;
+            let steps = self.pending_steps;
+            self.pending_steps = 0;
+            return steps;
+        }
+
+        self.accumulator += delta * self.time_scale;
+        let mut steps = (self.accumulator / period) as u32;
+        if steps > self.max_steps_per_frame {
+            // fell too far behind (e.g. after a hitch), drop the excess time
+            steps = self.max_steps_per_frame;
+            self.accumulator = 0.;
+        } else {
+            self.accumulator -= steps as f32 * period;
+        }
+        steps + std::mem::take(&mut self.pending_steps)
+    }
+
+    pub fn finish_frame(&mut self, steps: u32, delta: f32, period: f32) {
+        let sim_delta = steps as f32 * period;
+        self.steps_last_frame = steps;
+        if delta > 0. {
+            // exponential smoothing, the per-frame ratio is noisy
+            let alpha = 0.05;
+            self.real_time_factor += alpha * (sim_delta / delta - self.real_time_factor);
+        }
+    }
+}
+
+pub enum SimulationEvent {
+    Pause,
+    Resume,
+    TogglePause,
+    Step(u32),
+    SetTimeScale(f32),
+    SetMaxStepsPerFrame(u32),
+}
+
+pub fn simulation_event_system(
+    mut events: EventReader<SimulationEvent>,
+    mut control: ResMut<SimulationControl>,
+) {
+    for event in events.iter() {
+        match event {
+            SimulationEvent::Pause => control.paused = true,
+            SimulationEvent::Resume => control.paused = false,
+            SimulationEvent::TogglePause => control.paused = !control.paused,
+            SimulationEvent::Step(steps) => {
+                // stepping only makes sense while paused
+                control.paused = true;
+                control.pending_steps += steps;
+            }
+            SimulationEvent::SetTimeScale(scale) => control.time_scale = scale.max(0.),
+            SimulationEvent::SetMaxStepsPerFrame(steps) => control.max_steps_per_frame = *steps,
+        }
+    }
+}
+
+// replaces running the integrator in the fixed update schedule
+pub fn simulation_clock(world: &mut World) {
+    let delta = world.resource::<Time>().delta_seconds();
+    let period = world.resource::<FixedTime>().period.as_secs_f32();
+    let steps = world
+        .resource_mut::<SimulationControl>()
+        .steps_for_frame(delta, period);
+
+    let mut steps_taken = 0;
+    for _ in 0..steps {
+        let start = Instant::now();
+        integrator_schedule::<Joint>(world);
+        // the post step systems see the time at the end of the step
+        world.resource_mut::<SimulationControl>().elapsed += period;
+        world.run_schedule(PostStepSchedule);
+        if let Some(mut timings) = world.get_resource_mut::<PhysicsTimings>() {
+            timings.step += start.elapsed();
+        }
+        steps_taken += 1;
+        if !handle_divergence(world) {
+            break;
+        }
+    }
+
+    world
+        .resource_mut::<SimulationControl>()
+        .finish_frame(steps_taken, delta, period);
+}
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_schedule(PostStepSchedule, create_post_step_schedule())
+            .init_resource::<SimulationControl>()
+            .add_event::<SimulationEvent>()
+            .add_systems(
+                (simulation_event_system, simulation_clock)
+                    .chain()
+                    .in_base_set(CoreSet::FixedUpdate),
+            );
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use bevy::prelude::Resource;
+
+use crate::terrain::{read_grayscale_image, SurfacePoint};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceMaterial {
+    pub friction_scale: f32, // multiplies the tire and collider friction
+    pub rolling_resistance_scale: f32, // multiplies the tire rolling resistance (TireCondition)
+}
+
+impl Default for SurfaceMaterial {
+    fn default() -> Self {
+        Self {
+            friction_scale: 1.,
+            rolling_resistance_scale: 1.,
+        }
+    }
+}
+
+impl SurfaceMaterial {
+    pub fn new(friction_scale: f32, rolling_resistance_scale: f32) -> Self {
+        Self {
+            friction_scale,
+            rolling_resistance_scale,
+        }
+    }
+}
+
+pub enum SurfaceRegion {
+    // closed polygon of x, y points (absolute coordinates)
+    Polygon(Vec<[f32; 2]>),
+    // grid of cells that are in the region, index = iy * n_x + ix
+    Mask {
+        origin: [f32; 2], // x, y of the center of the first cell
+        spacing: f32,
+        n_x: usize,
+        n_y: usize,
+        cells: Vec<bool>,
+    },
+    // mesh terrain triangles with this tag
+    Tag(u32),
+}
+
+impl SurfaceRegion {
+    // rectangle from (x0, y0) to (x1, y1)
+    pub fn rectangle(x0: f32, y0: f32, x1: f32, y1: f32) -> Self {
+        SurfaceRegion::Polygon(vec![[x0, y0], [x1, y0], [x1, y1], [x0, y1]])
+    }
+
+    // grayscale image, pixels brighter than threshold (0..1) are in the region. Image columns
+    // are along x and rows along y, the first pixel is at the origin.
+    pub fn from_image(
+        path: impl AsRef<Path>,
+        origin: [f32; 2],
+        spacing: f32,
+        threshold: f32,
+    ) -> Result<Self, String> {
+        let (n_x, n_y, values) = read_grayscale_image(path)?;
+        Ok(SurfaceRegion::Mask {
+            origin,
+            spacing,
+            n_x,
+            n_y,
+            cells: values.iter().map(|value| *value > threshold).collect(),
+        })
+    }
+
+    pub fn contains(&self, surface: &SurfacePoint) -> bool {
+        let (x, y) = (surface.point.x, surface.point.y);
+        match self {
+            SurfaceRegion::Polygon(points) => {
+                // even-odd rule
+                let mut inside = false;
+                let mut j = points.len().wrapping_sub(1);
+                for i in 0..points.len() {
+                    let [xi, yi] = points[i];
+                    let [xj, yj] = points[j];
+                    if (yi > y) != (yj > y) && x < xi + (y - yi) / (yj - yi) * (xj - xi) {
+                        inside = !inside;
+                    }
+                    j = i;
+                }
+                inside
+            }
+            SurfaceRegion::Mask {
+                origin,
+                spacing,
+                n_x,
+                n_y,
+                cells,
+            } => {
+                let ix = ((x - origin[0]) / spacing).round();
+                let iy = ((y - origin[1]) / spacing).round();
+                if ix < 0. || iy < 0. || ix >= *n_x as f32 || iy >= *n_y as f32 {
+                    return false;
+                }
+                cells[iy as usize * n_x + ix as usize]
+            }
+            SurfaceRegion::Tag(tag) => surface.tag == Some(*tag),
+        }
+    }
+}
+
+// ground materials. Regions added later are on top of earlier ones, everything outside the
+// regions uses the default material.
+#[derive(Resource, Default)]
+pub struct SurfaceMap {
+    pub default: SurfaceMaterial,
+    pub regions: Vec<(SurfaceRegion, SurfaceMaterial)>,
+}
+
+impl SurfaceMap {
+    pub fn new(default: SurfaceMaterial) -> Self {
+        Self {
+            default,
+            regions: Vec::new(),
+        }
+    }
+
+    pub fn with_region(mut self, region: SurfaceRegion, material: SurfaceMaterial) -> Self {
+        self.regions.push((region, material));
+        self
+    }
+
+    pub fn material_at(&self, surface: &SurfacePoint) -> SurfaceMaterial {
+        self.regions
+            .iter()
+            .rev()
+            .find(|(region, _)| region.contains(surface))
+            .map_or(self.default, |(_, material)| *material)
+    }
+}
@@ -0,0 +1,374 @@
+use std::{fs::File, io::Read, path::Path};
+
+use bevy::{
+    prelude::{Mesh as BevyMesh, Resource},
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_resource::TextureFormat,
+        texture::{CompressedImageFormats, Image, ImageType, TextureFormatPixelInfo},
+    },
+};
+
+use crate::{sva::Vector, trimesh::TriMesh};
+
+// a point on the ground and the ground normal there, in absolute coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct SurfacePoint {
+    pub point: Vector,
+    pub normal: Vector,
+    pub tag: Option<u32>, // triangle tag of a mesh terrain
+}
+
+#[derive(Resource, Default)]
+pub enum Terrain {
+    #[default]
+    Flat, // the plane z = 0
+    Heightfield(Heightfield),
+    Mesh(MeshTerrain),
+}
+
+impl Terrain {
+    // the ground below a point (absolute coordinates)
+    pub fn surface_at(&self, point: Vector) -> SurfacePoint {
+        match self {
+            Terrain::Flat => SurfacePoint {
+                point: Vector::new(point.x, point.y, 0.),
+                normal: Vector::new(0., 0., 1.),
+                tag: None,
+            },
+            Terrain::Heightfield(heightfield) => heightfield.surface_at(point),
+            Terrain::Mesh(mesh) => mesh.surface_at(point),
+        }
+    }
+}
+
+// arbitrary triangle mesh ground (tunnels, bridges, overhangs). The surface below a point is
+// found with a downward ray starting probe_height above it, so a roof higher than that is
+// ignored.
+pub struct MeshTerrain {
+    pub mesh: TriMesh,
+    pub probe_height: f32,
+    pub max_depth: f32, // how far below the point the ray searches
+}
+
+impl MeshTerrain {
+    pub fn new(mesh: TriMesh) -> Self {
+        Self {
+            mesh,
+            probe_height: 1.,
+            max_depth: 100.,
+        }
+    }
+
+    pub fn from_obj(path: impl AsRef<Path>, y_up: bool) -> Result<Self, String> {
+        Ok(Self::new(TriMesh::from_obj(path, y_up)?))
+    }
+
+    pub fn surface_at(&self, point: Vector) -> SurfacePoint {
+        let down = Vector::new(0., 0., -1.);
+        let origin = point - self.probe_height * down;
+        match self
+            .mesh
+            .raycast(origin, down, self.probe_height + self.max_depth)
+        {
+            Some(hit) => SurfacePoint {
+                point: hit.point,
+                normal: hit.normal,
+                tag: self.mesh.tags[hit.triangle],
+            },
+            // no ground, far enough below that nothing touches it
+            None => SurfacePoint {
+                point: Vector::new(point.x, point.y, point.z - self.max_depth),
+                normal: Vector::new(0., 0., 1.),
+                tag: None,
+            },
+        }
+    }
+}
+
+// grayscale image as (width, height, values in 0..1), row by row from the first pixel
+pub fn read_grayscale_image(path: impl AsRef<Path>) -> Result<(usize, usize, Vec<f32>), String> {
+    let path = path.as_ref();
+    let mut buffer = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut buffer))
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+    let image = Image::from_buffer(
+        &buffer,
+        ImageType::Extension(extension),
+        CompressedImageFormats::NONE,
+        false,
+    )
+    .map_err(|e| format!("could not decode {}: {}", path.display(), e))?;
+
+    let size = image.size();
+    let (n_x, n_y) = (size.x as usize, size.y as usize);
+    // only the first channel is used. Bevy expands 8 bit images to rgba and keeps 16 bit ones
+    // as they are, in native byte order
+    let format = image.texture_descriptor.format;
+    let channel: fn(&[u8]) -> f32 = match format {
+        TextureFormat::R8Unorm
+        | TextureFormat::Rg8Unorm
+        | TextureFormat::Rgba8Unorm
+        | TextureFormat::Rgba8UnormSrgb => |pixel| pixel[0] as f32 / u8::MAX as f32,
+        TextureFormat::R16Uint | TextureFormat::Rg16Uint | TextureFormat::Rgba16Uint => {
+            |pixel| u16::from_ne_bytes([pixel[0], pixel[1]]) as f32 / u16::MAX as f32
+        }
+        TextureFormat::R32Float | TextureFormat::Rgba32Float => {
+            |pixel| f32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]])
+        }
+        _ => {
+            return Err(format!(
+                "unsupported pixel format {:?} in {}",
+                format,
+                path.display()
+            ))
+        }
+    };
+    let bytes_per_pixel = format.pixel_size();
+    if image.data.len() < n_x * n_y * bytes_per_pixel {
+        return Err(format!("truncated image data in {}", path.display()));
+    }
+    let values = (0..n_x * n_y)
+        .map(|ind| channel(&image.data[ind * bytes_per_pixel..]))
+        .collect();
+    Ok((n_x, n_y, values))
+}
+
+// regular grid of heights. Each cell is split into two triangles along the (0,0)-(1,1) diagonal,
+// the same triangles are used for contact and rendering.
+pub struct Heightfield {
+    pub origin: [f32; 2], // x, y of the first grid point
+    pub spacing: f32,
+    pub n_x: usize,
+    pub n_y: usize,
+    pub heights: Vec<f32>, // index = iy * n_x + ix
+}
+
+impl Heightfield {
+    pub fn new(origin: [f32; 2], spacing: f32, n_x: usize, n_y: usize, heights: Vec<f32>) -> Self {
+        if let Err(e) = Self::check_size(n_x, n_y, heights.len()) {
+            panic!("{}", e);
+        }
+        Self {
+            origin,
+            spacing,
+            n_x,
+            n_y,
+            heights,
+        }
+    }
+
+    // the loaders check this first, so bad files are an error rather than a panic
+    fn check_size(n_x: usize, n_y: usize, len: usize) -> Result<(), String> {
+        if n_x < 2 || n_y < 2 {
+            return Err(format!(
+                "heightfield needs at least 2x2 points, got {}x{}",
+                n_x, n_y
+            ));
+        }
+        if len != n_x * n_y {
+            return Err(format!(
+                "heightfield has {} heights for {}x{} points",
+                len, n_x, n_y
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn from_fn(
+        origin: [f32; 2],
+        spacing: f32,
+        n_x: usize,
+        n_y: usize,
+        height: impl Fn(f32, f32) -> f32,
+    ) -> Self {
+        let mut heights = Vec::with_capacity(n_x * n_y);
+        for iy in 0..n_y {
+            for ix in 0..n_x {
+                let x = origin[0] + ix as f32 * spacing;
+                let y = origin[1] + iy as f32 * spacing;
+                heights.push(height(x, y));
+            }
+        }
+        Self::new(origin, spacing, n_x, n_y, heights)
+    }
+
+    // square plane centered on the origin with a constant grade (rise / run) in x and y.
+    // Inclined surfaces use grade_x, banked surfaces use grade_y.
+    pub fn plane(size: f32, spacing: f32, grade_x: f32, grade_y: f32) -> Self {
+        let n = (size / spacing) as usize + 1;
+        let origin = [-size / 2., -size / 2.];
+        Self::from_fn(origin, spacing, n, n, |x, y| grade_x * x + grade_y * y)
+    }
+
+    // square patch of sinusoidal hills centered on the origin
+    pub fn hills(size: f32, spacing: f32, amplitude: f32, wavelength: f32) -> Self {
+        let n = (size / spacing) as usize + 1;
+        let origin = [-size / 2., -size / 2.];
+        let k = 2. * std::f32::consts::PI / wavelength;
+        Self::from_fn(origin, spacing, n, n, |x, y| {
+            amplitude * (k * x).sin() * (k * y).cos()
+        })
+    }
+
+    // grayscale image, black = 0, white = height_scale. Image columns are along x and rows along
+    // y, the first pixel is at the origin.
+    pub fn from_image(
+        path: impl AsRef<Path>,
+        origin: [f32; 2],
+        spacing: f32,
+        height_scale: f32,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let (n_x, n_y, values) = read_grayscale_image(path)?;
+        Self::check_size(n_x, n_y, values.len())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let heights = values.iter().map(|value| value * height_scale).collect();
+        Ok(Self::new(origin, spacing, n_x, n_y, heights))
+    }
+
+    // raw grid of heights, one row (constant y) per line, separated by commas or whitespace
+    pub fn from_csv(
+        path: impl AsRef<Path>,
+        origin: [f32; 2],
+        spacing: f32,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        let mut heights = Vec::new();
+        let mut n_x = 0;
+        let mut n_y = 0;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let row = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| format!("{} line {}: {}", path.display(), n_y + 1, e))?;
+            if n_y == 0 {
+                n_x = row.len();
+            } else if row.len() != n_x {
+                return Err(format!(
+                    "{} line {}: expected {} values, found {}",
+                    path.display(),
+                    n_y + 1,
+                    n_x,
+                    row.len()
+                ));
+            }
+            heights.extend(row);
+            n_y += 1;
+        }
+        Self::check_size(n_x, n_y, heights.len())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self::new(origin, spacing, n_x, n_y, heights))
+    }
+
+    fn height_index(&self, ix: usize, iy: usize) -> f32 {
+        self.heights[iy * self.n_x + ix]
+    }
+
+    // height and gradient of the triangle containing (x, y). Outside the grid the edge
+    // height continues flat.
+    pub fn height_gradient(&self, x: f32, y: f32) -> (f32, [f32; 2]) {
+        let gx = (x - self.origin[0]) / self.spacing;
+        let gy = (y - self.origin[1]) / self.spacing;
+        let max_x = (self.n_x - 1) as f32;
+        let max_y = (self.n_y - 1) as f32;
+        let inside = gx >= 0. && gx <= max_x && gy >= 0. && gy <= max_y;
+        let gx = gx.clamp(0., max_x);
+        let gy = gy.clamp(0., max_y);
+
+        let ix = (gx as usize).min(self.n_x - 2);
+        let iy = (gy as usize).min(self.n_y - 2);
+        let fx = gx - ix as f32;
+        let fy = gy - iy as f32;
+
+        let h00 = self.height_index(ix, iy);
+        let h10 = self.height_index(ix + 1, iy);
+        let h01 = self.height_index(ix, iy + 1);
+        let h11 = self.height_index(ix + 1, iy + 1);
+
+        let (height, dx, dy) = if fx >= fy {
+            // triangle (0,0) (1,0) (1,1)
+            (
+                h00 + fx * (h10 - h00) + fy * (h11 - h10),
+                h10 - h00,
+                h11 - h10,
+            )
+        } else {
+            // triangle (0,0) (1,1) (0,1)
+            (
+                h00 + fy * (h01 - h00) + fx * (h11 - h01),
+                h11 - h01,
+                h01 - h00,
+            )
+        };
+
+        if inside {
+            (height, [dx / self.spacing, dy / self.spacing])
+        } else {
+            (height, [0., 0.])
+        }
+    }
+
+    pub fn surface_at(&self, point: Vector) -> SurfacePoint {
+        let (height, [dhdx, dhdy]) = self.height_gradient(point.x, point.y);
+        SurfacePoint {
+            point: Vector::new(point.x, point.y, height),
+            normal: Vector::new(-dhdx, -dhdy, 1.).normalize(),
+            tag: None,
+        }
+    }
+
+    pub fn to_bevy_mesh(&self) -> BevyMesh {
+        let mut positions = Vec::with_capacity(self.n_x * self.n_y);
+        let mut normals = Vec::with_capacity(self.n_x * self.n_y);
+        let mut uvs = Vec::with_capacity(self.n_x * self.n_y);
+        for iy in 0..self.n_y {
+            for ix in 0..self.n_x {
+                let x = self.origin[0] + ix as f32 * self.spacing;
+                let y = self.origin[1] + iy as f32 * self.spacing;
+                positions.push([x, y, self.height_index(ix, iy)]);
+
+                // central differences for smooth shading
+                let h = |ix: usize, iy: usize| self.height_index(ix, iy);
+                let (x0, x1) = (ix.saturating_sub(1), (ix + 1).min(self.n_x - 1));
+                let (y0, y1) = (iy.saturating_sub(1), (iy + 1).min(self.n_y - 1));
+                let dhdx = (h(x1, iy) - h(x0, iy)) / ((x1 - x0) as f32 * self.spacing);
+                let dhdy = (h(ix, y1) - h(ix, y0)) / ((y1 - y0) as f32 * self.spacing);
+                let normal = Vector::new(-dhdx, -dhdy, 1.).normalize();
+                normals.push([normal.x, normal.y, normal.z]);
+
+                uvs.push([
+                    ix as f32 / (self.n_x - 1) as f32,
+                    iy as f32 / (self.n_y - 1) as f32,
+                ]);
+            }
+        }
+
+        let mut indices = Vec::with_capacity(6 * (self.n_x - 1) * (self.n_y - 1));
+        for iy in 0..self.n_y - 1 {
+            for ix in 0..self.n_x - 1 {
+                let i00 = (iy * self.n_x + ix) as u32;
+                let i10 = i00 + 1;
+                let i01 = i00 + self.n_x as u32;
+                let i11 = i01 + 1;
+                indices.extend([i00, i10, i11, i00, i11, i01]);
+            }
+        }
+
+        let mut mesh = BevyMesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(BevyMesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(BevyMesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(BevyMesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
@@ -0,0 +1,578 @@
+use std::{fs::File, io::Read, path::Path};
+
+use bevy::{
+    prelude::Mesh as BevyMesh,
+    render::mesh::{Indices, PrimitiveTopology},
+};
+
+use crate::sva::Vector;
+
+// triangles per bvh leaf
+const LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeshHit {
+    pub point: Vector,
+    pub normal: Vector,
+    pub distance: f32, // along the ray, or to the query point
+    pub triangle: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Aabb {
+    min: Vector,
+    max: Vector,
+}
+
+impl Aabb {
+    fn empty() -> Self {
+        Self {
+            min: Vector::repeat(f32::MAX),
+            max: Vector::repeat(f32::MIN),
+        }
+    }
+
+    fn grow(&mut self, point: Vector) {
+        self.min = self.min.inf(&point);
+        self.max = self.max.sup(&point);
+    }
+
+    // entry distance along a ray, if the ray hits the box before max_t
+    fn ray_entry(&self, origin: Vector, inv_dir: Vector, max_t: f32) -> Option<f32> {
+        let mut t_min = 0.0_f32;
+        let mut t_max = max_t;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+        }
+        (t_min <= t_max).then_some(t_min)
+    }
+
+    fn distance_squared(&self, point: Vector) -> f32 {
+        let clamped = point.sup(&self.min).inf(&self.max);
+        (point - clamped).norm_squared()
+    }
+}
+
+enum BvhNode {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        count: usize,
+    },
+    Inner {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Inner { bounds, .. } => bounds,
+        }
+    }
+}
+
+// triangle mesh with a bounding volume hierarchy for ray and closest point queries
+pub struct TriMesh {
+    pub vertices: Vec<Vector>,
+    pub triangles: Vec<[usize; 3]>,
+    pub tags: Vec<Option<u32>>, // per triangle, e.g. the obj material
+    pub tag_names: Vec<String>, // index = tag
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>, // triangle indices, grouped by leaf
+}
+
+impl TriMesh {
+    pub fn new(vertices: Vec<Vector>, triangles: Vec<[usize; 3]>) -> Result<Self, String> {
+        let tags = vec![None; triangles.len()];
+        Self::from_parts(vertices, triangles, tags, Vec::new())
+    }
+
+    // zero area triangles (common in exported meshes) have no normal, they are dropped
+    fn from_parts(
+        vertices: Vec<Vector>,
+        triangles: Vec<[usize; 3]>,
+        tags: Vec<Option<u32>>,
+        tag_names: Vec<String>,
+    ) -> Result<Self, String> {
+        if let Some((ind, triangle)) = triangles
+            .iter()
+            .enumerate()
+            .find(|(_, triangle)| triangle.iter().any(|&vertex| vertex >= vertices.len()))
+        {
+            return Err(format!(
+                "triangle {} {:?} refers to a vertex past the {} vertices",
+                ind,
+                triangle,
+                vertices.len()
+            ));
+        }
+        let (triangles, tags) = triangles
+            .into_iter()
+            .zip(tags)
+            .filter(|([a, b, c], _)| {
+                let (ab, ac) = (vertices[*b] - vertices[*a], vertices[*c] - vertices[*a]);
+                ab.cross(&ac).norm() > f32::EPSILON * ab.norm() * ac.norm()
+            })
+            .unzip();
+        let mut mesh = Self {
+            vertices,
+            triangles,
+            tags,
+            tag_names,
+            nodes: Vec::new(),
+            order: Vec::new(),
+        };
+        mesh.build_bvh();
+        Ok(mesh)
+    }
+
+    // wavefront obj (vertices, faces and materials only). Polygons are split into triangle
+    // fans, and each triangle is tagged with its usemtl material (untagged before the first one).
+    // Set y_up for files that use y as the up axis, they are rotated to z up.
+    pub fn from_obj(path: impl AsRef<Path>, y_up: bool) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        let mut tags = Vec::new();
+        let mut tag_names: Vec<String> = Vec::new();
+        let mut tag = None;
+        for (line_number, line) in text.lines().enumerate() {
+            let error =
+                |message: &str| format!("{} line {}: {}", path.display(), line_number + 1, message);
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let values = tokens
+                        .take(3)
+                        .map(|token| token.parse::<f32>())
+                        .collect::<Result<Vec<f32>, _>>()
+                        .map_err(|e| error(&e.to_string()))?;
+                    if values.len() != 3 {
+                        return Err(error("vertex needs 3 coordinates"));
+                    }
+                    vertices.push(if y_up {
+                        Vector::new(values[0], -values[2], values[1])
+                    } else {
+                        Vector::new(values[0], values[1], values[2])
+                    });
+                }
+                Some("f") => {
+                    // "f 1 2 3", "f 1/1 2/2 3/3", "f 1//1 ...", negative indices are relative
+                    let indices = tokens
+                        .map(|token| {
+                            let index: i64 = token
+                                .split('/')
+                                .next()
+                                .unwrap_or("")
+                                .parse()
+                                .map_err(|_| error("bad face index"))?;
+                            let index = if index < 0 {
+                                vertices.len() as i64 + index
+                            } else {
+                                index - 1
+                            };
+                            if index < 0 || index as usize >= vertices.len() {
+                                return Err(error("face index out of range"));
+                            }
+                            Ok(index as usize)
+                        })
+                        .collect::<Result<Vec<usize>, String>>()?;
+                    for i in 1..indices.len().saturating_sub(1) {
+                        triangles.push([indices[0], indices[i], indices[i + 1]]);
+                        tags.push(tag);
+                    }
+                }
+                Some("usemtl") => {
+                    let name = tokens.next().unwrap_or("").to_string();
+                    tag = Some(
+                        match tag_names.iter().position(|tag_name| *tag_name == name) {
+                            Some(ind) => ind as u32,
+                            None => {
+                                tag_names.push(name);
+                                tag_names.len() as u32 - 1
+                            }
+                        },
+                    );
+                }
+                _ => {} // normals, texture coordinates, groups, comments
+            }
+        }
+        Self::from_parts(vertices, triangles, tags, tag_names)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<u32> {
+        self.tag_names
+            .iter()
+            .position(|tag_name| tag_name == name)
+            .map(|ind| ind as u32)
+    }
+
+    fn triangle_vertices(&self, triangle: usize) -> [Vector; 3] {
+        let [a, b, c] = self.triangles[triangle];
+        [self.vertices[a], self.vertices[b], self.vertices[c]]
+    }
+
+    pub fn triangle_normal(&self, triangle: usize) -> Vector {
+        let [a, b, c] = self.triangle_vertices(triangle);
+        (b - a).cross(&(c - a)).normalize()
+    }
+
+    fn build_bvh(&mut self) {
+        self.order = (0..self.triangles.len()).collect();
+        self.nodes.clear();
+        if !self.triangles.is_empty() {
+            let centroids: Vec<Vector> = (0..self.triangles.len())
+                .map(|triangle| {
+                    let [a, b, c] = self.triangle_vertices(triangle);
+                    (a + b + c) / 3.
+                })
+                .collect();
+            let count = self.order.len();
+            self.build_node(&centroids, 0, count);
+        }
+    }
+
+    // builds the node for order[start..start + count], returns its index
+    fn build_node(&mut self, centroids: &[Vector], start: usize, count: usize) -> usize {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &triangle in self.order[start..start + count].iter() {
+            for vertex in self.triangle_vertices(triangle) {
+                bounds.grow(vertex);
+            }
+            centroid_bounds.grow(centroids[triangle]);
+        }
+
+        let index = self.nodes.len();
+        if count <= LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf {
+                bounds,
+                start,
+                count,
+            });
+            return index;
+        }
+
+        // median split along the longest axis of the centroids
+        let axis = (centroid_bounds.max - centroid_bounds.min).imax();
+        self.order[start..start + count]
+            .sort_by(|&a, &b| centroids[a][axis].total_cmp(&centroids[b][axis]));
+        let half = count / 2;
+
+        // placeholder, filled in once the children exist
+        self.nodes.push(BvhNode::Leaf {
+            bounds,
+            start,
+            count,
+        });
+        let left = self.build_node(centroids, start, half);
+        let right = self.build_node(centroids, start + half, count - half);
+        self.nodes[index] = BvhNode::Inner {
+            bounds,
+            left,
+            right,
+        };
+        index
+    }
+
+    // moller-trumbore, two sided
+    fn ray_triangle(&self, triangle: usize, origin: Vector, dir: Vector) -> Option<f32> {
+        let [a, b, c] = self.triangle_vertices(triangle);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = dir.cross(&edge2);
+        let det = edge1.dot(&p);
+        if det.abs() < 1.0e-9 {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let s = origin - a;
+        let u = s.dot(&p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = dir.dot(&q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = edge2.dot(&q) * inv_det;
+        (t >= 0.).then_some(t)
+    }
+
+    // nearest hit along a ray (dir must be normalized). The normal faces the ray origin.
+    pub fn raycast(&self, origin: Vector, dir: Vector, max_distance: f32) -> Option<MeshHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_dir = dir.map(|x| 1. / x);
+        let mut best: Option<(f32, usize)> = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let max_t = best.map_or(max_distance, |(t, _)| t);
+            if self.nodes[node]
+                .bounds()
+                .ray_entry(origin, inv_dir, max_t)
+                .is_none()
+            {
+                continue;
+            }
+            match self.nodes[node] {
+                BvhNode::Leaf { start, count, .. } => {
+                    for &triangle in self.order[start..start + count].iter() {
+                        if let Some(t) = self.ray_triangle(triangle, origin, dir) {
+                            if t <= max_distance && best.map_or(true, |(best_t, _)| t < best_t) {
+                                best = Some((t, triangle));
+                            }
+                        }
+                    }
+                }
+                BvhNode::Inner { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+
+        best.map(|(t, triangle)| {
+            let mut normal = self.triangle_normal(triangle);
+            if normal.dot(&dir) > 0. {
+                normal = -normal;
+            }
+            MeshHit {
+                point: origin + t * dir,
+                normal,
+                distance: t,
+                triangle,
+            }
+        })
+    }
+
+    // closest point on a triangle (real-time collision detection, ericson)
+    fn closest_point_triangle(&self, triangle: usize, p: Vector) -> Vector {
+        let [a, b, c] = self.triangle_vertices(triangle);
+        let ab = b - a;
+        let ac = c - a;
+        let ap = p - a;
+        let d1 = ab.dot(&ap);
+        let d2 = ac.dot(&ap);
+        if d1 <= 0. && d2 <= 0. {
+            return a;
+        }
+        let bp = p - b;
+        let d3 = ab.dot(&bp);
+        let d4 = ac.dot(&bp);
+        if d3 >= 0. && d4 <= d3 {
+            return b;
+        }
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0. && d1 >= 0. && d3 <= 0. {
+            return a + d1 / (d1 - d3) * ab;
+        }
+        let cp = p - c;
+        let d5 = ab.dot(&cp);
+        let d6 = ac.dot(&cp);
+        if d6 >= 0. && d5 <= d6 {
+            return c;
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0. && d2 >= 0. && d6 <= 0. {
+            return a + d2 / (d2 - d6) * ac;
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0. && (d4 - d3) >= 0. && (d5 - d6) >= 0. {
+            return b + (d4 - d3) / ((d4 - d3) + (d5 - d6)) * (c - b);
+        }
+        let denom = 1. / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+
+    // closest point on the mesh within max_distance. The normal faces the query point.
+    pub fn closest_point(&self, point: Vector, max_distance: f32) -> Option<MeshHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best: Option<(f32, Vector, usize)> = None; // squared distance
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let limit = best.map_or(max_distance * max_distance, |(d, _, _)| d);
+            if self.nodes[node].bounds().distance_squared(point) > limit {
+                continue;
+            }
+            match self.nodes[node] {
+                BvhNode::Leaf { start, count, .. } => {
+                    for &triangle in self.order[start..start + count].iter() {
+                        let closest = self.closest_point_triangle(triangle, point);
+                        let distance = (point - closest).norm_squared();
+                        if distance <= limit && best.map_or(true, |(d, _, _)| distance < d) {
+                            best = Some((distance, closest, triangle));
+                        }
+                    }
+                }
+                BvhNode::Inner { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+
+        best.map(|(distance, closest, triangle)| {
+            let mut normal = self.triangle_normal(triangle);
+            if normal.dot(&(point - closest)) < 0. {
+                normal = -normal;
+            }
+            MeshHit {
+                point: closest,
+                normal,
+                distance: distance.sqrt(),
+                triangle,
+            }
+        })
+    }
+
+    // flat shaded, each triangle gets its own vertices
+    pub fn to_bevy_mesh(&self) -> BevyMesh {
+        let mut positions = Vec::with_capacity(3 * self.triangles.len());
+        let mut normals = Vec::with_capacity(3 * self.triangles.len());
+        let mut uvs = Vec::with_capacity(3 * self.triangles.len());
+        for triangle in 0..self.triangles.len() {
+            let normal = self.triangle_normal(triangle);
+            for vertex in self.triangle_vertices(triangle) {
+                positions.push([vertex.x, vertex.y, vertex.z]);
+                normals.push([normal.x, normal.y, normal.z]);
+                uvs.push([vertex.x, vertex.y]);
+            }
+        }
+        let indices = (0..positions.len() as u32).collect();
+
+        let mut mesh = BevyMesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(BevyMesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(BevyMesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(BevyMesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // n x n grid of unit squares, two triangles each, with the given height
+    fn grid(n: usize, height: impl Fn(f32, f32) -> f32) -> TriMesh {
+        let mut vertices = Vec::new();
+        for i in 0..=n {
+            for j in 0..=n {
+                let (x, y) = (i as f32, j as f32);
+                vertices.push(Vector::new(x, y, height(x, y)));
+            }
+        }
+        let mut triangles = Vec::new();
+        let index = |i: usize, j: usize| i * (n + 1) + j;
+        for i in 0..n {
+            for j in 0..n {
+                triangles.push([index(i, j), index(i + 1, j), index(i + 1, j + 1)]);
+                triangles.push([index(i, j), index(i + 1, j + 1), index(i, j + 1)]);
+            }
+        }
+        TriMesh::new(vertices, triangles).unwrap()
+    }
+
+    fn bumps(x: f32, y: f32) -> f32 {
+        0.5 * (x * 0.7).sin() * (y * 1.3).cos()
+    }
+
+    #[test]
+    fn raycast_hits_a_flat_grid() {
+        let mesh = grid(8, |_, _| 0.);
+        let hit = mesh
+            .raycast(Vector::new(1.3, 2.7, 5.), -Vector::z(), 10.)
+            .unwrap();
+        assert!((hit.point - Vector::new(1.3, 2.7, 0.)).norm() < 1.0e-5);
+        assert!((hit.distance - 5.).abs() < 1.0e-5);
+        assert!((hit.normal - Vector::z()).norm() < 1.0e-5);
+
+        // from below the normal faces the other way
+        let hit = mesh
+            .raycast(Vector::new(1.3, 2.7, -1.), Vector::z(), 10.)
+            .unwrap();
+        assert!((hit.normal + Vector::z()).norm() < 1.0e-5);
+
+        // too short, off the side, or pointing away
+        let origin = Vector::new(1.3, 2.7, 5.);
+        assert!(mesh.raycast(origin, -Vector::z(), 4.).is_none());
+        assert!(mesh
+            .raycast(Vector::new(-1., 2.7, 5.), -Vector::z(), 10.)
+            .is_none());
+        assert!(mesh.raycast(origin, Vector::z(), 10.).is_none());
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        let mesh = grid(10, bumps);
+        for k in 0..50 {
+            let origin = Vector::new(0.37 * k as f32 % 10., 0.73 * k as f32 % 10., 3.);
+            let dir = Vector::new(0.1, -0.2, -1.).normalize();
+            let brute = (0..mesh.triangles.len())
+                .filter_map(|triangle| mesh.ray_triangle(triangle, origin, dir))
+                .min_by(|a, b| a.total_cmp(b));
+            let hit = mesh.raycast(origin, dir, 10.).map(|hit| hit.distance);
+            match (hit, brute) {
+                (Some(hit), Some(brute)) => assert!((hit - brute).abs() < 1.0e-5),
+                (hit, brute) => assert_eq!(hit.is_some(), brute.is_some()),
+            }
+        }
+    }
+
+    #[test]
+    fn closest_point_on_a_flat_grid() {
+        let mesh = grid(8, |_, _| 0.);
+        let hit = mesh.closest_point(Vector::new(2.5, 3.5, 0.4), 1.).unwrap();
+        assert!((hit.point - Vector::new(2.5, 3.5, 0.)).norm() < 1.0e-5);
+        assert!((hit.distance - 0.4).abs() < 1.0e-5);
+        assert!((hit.normal - Vector::z()).norm() < 1.0e-5);
+
+        // past the edge the closest point is on the boundary
+        let hit = mesh.closest_point(Vector::new(-0.3, 4.5, 0.), 1.).unwrap();
+        assert!((hit.point - Vector::new(0., 4.5, 0.)).norm() < 1.0e-5);
+
+        assert!(mesh.closest_point(Vector::new(2.5, 3.5, 2.), 1.).is_none());
+    }
+
+    #[test]
+    fn closest_point_matches_brute_force() {
+        let mesh = grid(10, bumps);
+        for k in 0..50 {
+            let point = Vector::new(
+                0.41 * k as f32 % 11. - 0.5,
+                0.67 * k as f32 % 11. - 0.5,
+                0.3 * (k % 5) as f32 - 0.6,
+            );
+            let brute = (0..mesh.triangles.len())
+                .map(|triangle| (point - mesh.closest_point_triangle(triangle, point)).norm())
+                .min_by(|a, b| a.total_cmp(b))
+                .unwrap();
+            let hit = mesh.closest_point(point, 5.).unwrap();
+            assert!((hit.distance - brute).abs() < 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn new_rejects_bad_indices_and_drops_degenerate_triangles() {
+        let vertices = vec![Vector::zeros(), Vector::x(), Vector::y()];
+        assert!(TriMesh::new(vertices.clone(), vec![[0, 1, 3]]).is_err());
+        let mesh = TriMesh::new(vertices, vec![[0, 1, 2], [0, 1, 1]]).unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+    }
+}
@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{
+    divergence::{RollbackAppExt, RollbackState},
+    serialize::{GustDef, TurbulenceDef, WindDef},
+    simulation::{PostStepSchedule, PostStepSet, SimulationControl},
+    sva::Vector,
+};
+
+// random turbulence with a first order (dryden longitudinal) spectrum on each axis, the same
+// everywhere. The correlation time is length_scale / mean wind speed.
+#[derive(Debug, Clone)]
+pub struct Turbulence {
+    intensity: Vector, // standard deviation on each absolute axis, m/s
+    length_scale: f32,
+    seed: u64,
+    pub velocity: Vector,
+}
+
+impl Turbulence {
+    pub fn new(intensity: Vector, length_scale: f32, seed: u64) -> Self {
+        Self {
+            intensity,
+            length_scale,
+            seed: seed.max(1),
+            velocity: Vector::zeros(),
+        }
+    }
+
+    pub fn from_def(turbulence_def: &TurbulenceDef) -> Self {
+        Self::new(
+            Vector::from(turbulence_def.intensity),
+            turbulence_def.length_scale,
+            turbulence_def.seed,
+        )
+    }
+
+    // xorshift, uniform in (0, 1]
+    fn uniform(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        ((self.seed >> 40) as f32 + 1.) / (1_u64 << 24) as f32
+    }
+
+    // standard normal, box-muller
+    fn normal(&mut self) -> f32 {
+        let (u1, u2) = (self.uniform(), self.uniform());
+        (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+    }
+
+    // exact update of the filtered noise over a step
+    fn update(&mut self, mean_speed: f32, dt: f32) {
+        let decay = (-dt * mean_speed.max(1.) / self.length_scale).exp();
+        let noise = (1. - decay * decay).sqrt();
+        for axis in 0..3 {
+            let sample = self.normal();
+            self.velocity[axis] =
+                decay * self.velocity[axis] + noise * self.intensity[axis] * sample;
+        }
+    }
+}
+
+// discrete gust: ramps in and out with a 1 - cos shape, optionally only inside an x, y rectangle
+// (a crosswind section of track, for iso 12021 style tests)
+#[derive(Debug, Clone)]
+pub struct Gust {
+    velocity: Vector,
+    start: f32,
+    duration: Option<f32>, // None holds the gust for good
+    rise_time: f32,
+    region: Option<[f32; 4]>, // x min, x max, y min, y max
+}
+
+impl Gust {
+    pub fn new(velocity: Vector, start: f32, duration: Option<f32>, rise_time: f32) -> Self {
+        Self {
+            velocity,
+            start,
+            duration,
+            rise_time,
+            region: None,
+        }
+    }
+
+    pub fn with_region(mut self, region: [f32; 4]) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn from_def(gust_def: &GustDef) -> Self {
+        let gust = Self::new(
+            Vector::from(gust_def.velocity),
+            gust_def.start,
+            gust_def.duration,
+            gust_def.rise_time,
+        );
+        match gust_def.region {
+            Some(region) => gust.with_region(region),
+            None => gust,
+        }
+    }
+
+    fn ramp(&self, time: f32) -> f32 {
+        if self.rise_time <= 0. {
+            return if time >= 0. { 1. } else { 0. };
+        }
+        let fraction = (time / self.rise_time).clamp(0., 1.);
+        0.5 * (1. - (PI * fraction).cos())
+    }
+
+    fn velocity_at(&self, point: Vector, time: f32) -> Vector {
+        if let Some([x_min, x_max, y_min, y_max]) = self.region {
+            if point.x < x_min || point.x > x_max || point.y < y_min || point.y > y_max {
+                return Vector::zeros();
+            }
+        }
+        let time = time - self.start;
+        let ramp_out = self
+            .duration
+            .map_or(1., |duration| self.ramp(duration - time));
+        self.ramp(time).min(ramp_out) * self.velocity
+    }
+}
+
+// air velocity over the world, in absolute coordinates: steady wind plus turbulence plus gusts.
+// Anything with an aero component samples it.
+#[derive(Resource, Debug, Clone)]
+pub struct Wind {
+    pub velocity: Vector, // steady
+    pub turbulence: Option<Turbulence>,
+    pub gusts: Vec<Gust>,
+    pub time: f32, // simulation time, set from SimulationControl every step
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self::new(Vector::zeros())
+    }
+}
+
+impl Wind {
+    pub fn new(velocity: Vector) -> Self {
+        Self {
+            velocity,
+            turbulence: None,
+            gusts: Vec::new(),
+            time: 0.,
+        }
+    }
+
+    pub fn with_turbulence(mut self, turbulence: Turbulence) -> Self {
+        self.turbulence = Some(turbulence);
+        self
+    }
+
+    pub fn with_gust(mut self, gust: Gust) -> Self {
+        self.gusts.push(gust);
+        self
+    }
+
+    pub fn from_def(wind_def: &WindDef) -> Self {
+        let mut wind = Self::new(Vector::from(wind_def.velocity));
+        wind.turbulence = wind_def.turbulence.as_ref().map(Turbulence::from_def);
+        wind.gusts = wind_def.gusts.iter().map(Gust::from_def).collect();
+        wind
+    }
+
+    pub fn velocity_at(&self, point: Vector) -> Vector {
+        let turbulence = self
+            .turbulence
+            .as_ref()
+            .map_or(Vector::zeros(), |turbulence| turbulence.velocity);
+        let gusts = self.gusts.iter().fold(Vector::zeros(), |sum, gust| {
+            sum + gust.velocity_at(point, self.time)
+        });
+        self.velocity + turbulence + gusts
+    }
+}
+
+impl RollbackState for Wind {
+    type State = (f32, Option<(Vector, u64)>);
+    fn save(&self) -> Self::State {
+        let turbulence = self
+            .turbulence
+            .as_ref()
+            .map(|turbulence| (turbulence.velocity, turbulence.seed));
+        (self.time, turbulence)
+    }
+    fn restore(&mut self, state: &Self::State) {
+        self.time = state.0;
+        if let (Some(turbulence), Some((velocity, seed))) = (self.turbulence.as_mut(), state.1) {
+            turbulence.velocity = velocity;
+            turbulence.seed = seed;
+        }
+    }
+}
+
+// wind time and turbulence, once per step
+pub fn wind_system(
+    mut wind: ResMut<Wind>,
+    simulation: Res<SimulationControl>,
+    fixed_time: Res<FixedTime>,
+) {
+    let dt = fixed_time.period.as_secs_f32();
+    let mean_speed = wind.velocity.norm();
+    wind.time = simulation.elapsed;
+    if let Some(turbulence) = wind.turbulence.as_mut() {
+        turbulence.update(mean_speed, dt);
+    }
+}
+
+// owns the Wind resource. Add after the SimulationPlugin and DivergencePlugin, the wind is
+// updated after every step and rolled back with the joints.
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Wind>() // still air unless a wind was inserted before
+            .add_rollback_resource::<Wind>()
+            .add_system(
+                wind_system
+                    .in_set(PostStepSet::Update)
+                    .in_schedule(PostStepSchedule),
+            );
+    }
+}